@@ -0,0 +1,240 @@
+use std::rc::Rc;
+
+use actix_web::{
+    dev::{
+        Extensions,
+        Service,
+        ServiceRequest,
+        ServiceResponse,
+        Transform,
+    },
+    web::Data,
+    HttpRequest,
+};
+use futures::future::LocalBoxFuture;
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    sign::Signer,
+    ssl::{
+        SslAcceptor,
+        SslAcceptorBuilder,
+        SslFiletype,
+        SslMethod,
+        SslVerifyMode,
+    },
+};
+
+use crate::config::{
+    Admin,
+    AdminAuth,
+    Config,
+};
+
+pub const TIMESTAMP_HEADER: &str = "X-Hydrogen-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Hydrogen-Signature";
+
+/// Path prefixes of the management endpoints.
+const MANAGEMENT_PREFIXES: [&str; 3] = ["/connections", "/rooms", "/principals"];
+
+/// Subject of the client certificate presented on an admin connection,
+/// formatted as comma separated `$key=$value` pairs, e.g. `CN=backend,O=acme`.
+#[derive(Debug, Clone)]
+pub struct PeerSubject(pub String);
+
+/// Builds the TLS acceptor for the admin bind that requires clients to present
+/// a certificate signed by `ca_cert`.
+pub fn make_acceptor(
+    ca_cert: &str,
+    cert: &str,
+    key: &str,
+) -> std::result::Result<SslAcceptorBuilder, openssl::error::ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_ca_file(ca_cert)?;
+    builder.set_certificate_chain_file(cert)?;
+    builder.set_private_key_file(key, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+    builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    Ok(builder)
+}
+
+/// Connection hook of the admin server that makes the subject of the client
+/// certificate available to the handlers.
+pub fn on_connect(conn: &dyn std::any::Any, ext: &mut Extensions) {
+    let stream = match conn.downcast_ref::<actix_tls::accept::openssl::TlsStream<actix_web::rt::net::TcpStream>>() {
+        | Some(v) => v,
+        | None => return,
+    };
+    if let Some(cert) = stream.ssl().peer_certificate() {
+        let subject = cert
+            .subject_name()
+            .entries()
+            .map(|entry| {
+                format!(
+                    "{}={}",
+                    entry.object().nid().short_name().unwrap_or("?"),
+                    entry.data().to_string().unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        ext.insert(PeerSubject(subject));
+    }
+}
+
+/// Whether the path belongs to the management endpoints.
+pub fn is_management(path: &str) -> bool {
+    MANAGEMENT_PREFIXES.iter().any(|v| {
+        path.strip_prefix(v)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Middleware admitting only requests that pass the configured admin auth to
+/// the services it wraps.
+pub struct Authorize;
+
+impl<S, B> Transform<S, ServiceRequest> for Authorize
+where
+    S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=actix_web::Error>+'static,
+    B: 'static,
+{
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<std::result::Result<Self::Transform, Self::InitError>>;
+    type InitError = ();
+    type Response = ServiceResponse<B>;
+    type Transform = AuthorizeMiddleware<S>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(AuthorizeMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AuthorizeMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthorizeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=actix_web::Error>+'static,
+    B: 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+    type Response = ServiceResponse<B>;
+
+    actix_web::dev::forward_ready!(service);
+
+    /// Reads the body the HMAC signature covers and hands it on to the
+    /// wrapped service once the request is authorized.
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let config = req
+                .app_data::<Data<Config>>()
+                .cloned()
+                .ok_or_else(|| actix_web::error::ErrorInternalServerError("missing config"))?;
+            if config.server.admin.is_some() {
+                let (http_req, payload) = req.parts_mut();
+                let body = crate::handlers::connection::read_body(payload.take(), config.server.max_out_message_size)
+                    .await?
+                    .freeze();
+                authorize(&config.server.admin, http_req, &body)?;
+                let (_, mut payload) = actix_http::h1::Payload::create(true);
+                payload.unread_data(body);
+                req.set_payload(payload.into());
+            }
+            service.call(req).await
+        })
+    }
+}
+
+/// Checks whether the request is allowed to use the management endpoints.
+/// Requests pass unconditionally if no admin auth is configured.
+fn authorize(admin: &std::option::Option<Admin>, req: &HttpRequest, body: &[u8]) -> Result<(), actix_web::Error> {
+    let auth = match admin {
+        | Some(v) => &v.auth,
+        | None => return Ok(()),
+    };
+    let allowed = match auth {
+        | AdminAuth::Bearer { tokens } => {
+            match req
+                .headers()
+                .get(actix_web::http::header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+            {
+                | Some(token) => tokens.iter().any(|v| equals(v.as_bytes(), token.as_bytes())),
+                | None => false,
+            }
+        },
+        | AdminAuth::Hmac { secret, max_skew_sec } => verify_signature(secret, *max_skew_sec, req, body),
+        | AdminAuth::Mtls { subjects, .. } => match req.conn_data::<PeerSubject>() {
+            | Some(subject) => subjects.contains(&subject.0),
+            | None => false,
+        },
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(actix_web::error::ErrorUnauthorized("unauthorized"))
+    }
+}
+
+/// Verifies the hex encoded HMAC-SHA256 signature in the `SIGNATURE_HEADER`
+/// over `"$timestamp\n$method\n$path_and_query\n$body"`, where the timestamp
+/// (unix seconds) is taken from the `TIMESTAMP_HEADER` and must not be older
+/// or newer than `max_skew_sec`.
+fn verify_signature(secret: &str, max_skew_sec: u16, req: &HttpRequest, body: &[u8]) -> bool {
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let (timestamp, signature) = match (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER)) {
+        | (Some(t), Some(s)) => (t, s),
+        | _ => return false,
+    };
+    match timestamp.parse::<i64>() {
+        | Ok(v) if (chrono::Utc::now().timestamp() - v).abs() <= max_skew_sec.into() => {},
+        | _ => return false,
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|v| v.as_str())
+        .unwrap_or_else(|| req.path());
+    let expected = PKey::hmac(secret.as_bytes())
+        .and_then(|key| {
+            let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+            signer.update(format!("{}\n{}\n{}\n", timestamp, req.method(), path).as_bytes())?;
+            signer.update(body)?;
+            signer.sign_to_vec()
+        })
+        .map(|v| v.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    match expected {
+        | Ok(v) => equals(v.as_bytes(), signature.to_ascii_lowercase().as_bytes()),
+        | Err(_) => false,
+    }
+}
+
+/// Compares secrets in constant time.
+fn equals(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn management_paths_are_recognized() {
+        assert!(is_management("/connections"));
+        assert!(is_management("/connections/_broadcast"));
+        assert!(is_management("/rooms/a/connections"));
+        assert!(is_management("/principals/p/_send"));
+        assert!(!is_management("/connectionsx"));
+        assert!(!is_management("/ws/connections"));
+        assert!(!is_management("/health"));
+        assert!(!is_management("/metrics"));
+    }
+}
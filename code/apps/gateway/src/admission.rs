@@ -0,0 +1,37 @@
+use std::sync::{
+    atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+    Arc,
+};
+
+/// Number of connections admitted on this instance.
+#[derive(Debug, Default)]
+pub struct InstanceConnections {
+    count: AtomicUsize,
+}
+
+impl InstanceConnections {
+    /// Claims a slot for a new connection unless `max` connections are
+    /// already admitted. The slot is released when it is dropped.
+    pub fn try_acquire(self: Arc<Self>, max: std::option::Option<usize>) -> std::option::Option<AdmissionSlot> {
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| match max {
+                | Some(max) if v >= max => None,
+                | _ => Some(v + 1),
+            })
+            .ok()?;
+        Some(AdmissionSlot(self))
+    }
+}
+
+/// A connection admitted on this instance.
+#[derive(Debug)]
+pub struct AdmissionSlot(Arc<InstanceConnections>);
+
+impl Drop for AdmissionSlot {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -10,6 +10,9 @@ pub struct CallArgs {
 
 impl CallArgs {
     pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        match &self.command {
+            | Command::Serve { config } => config.validate()?,
+        }
         Ok(())
     }
 }
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::HttpRequest;
+
+use crate::routes::{
+    AuthorizerRejection,
+    AuthorizerResponse,
+};
+
+type AuthorizerResult = std::result::Result<AuthorizerResponse, AuthorizerRejection>;
+
+/// Cache for authorizer results of this instance, keyed by a hash of the
+/// credential the client presents and the endpoint it connects to.
+pub struct AuthorizerCache {
+    settings: crate::config::AuthorizerCache,
+    entries: Mutex<HashMap<String, (Instant, AuthorizerResult)>>,
+}
+
+impl AuthorizerCache {
+    pub fn new(settings: crate::config::AuthorizerCache) -> Self {
+        Self {
+            settings,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Derives the cache key for the request. Requests without a credential
+    /// are not cached.
+    pub fn key(&self, req: &HttpRequest, endpoint: &str) -> std::option::Option<String> {
+        let credential = crate::jwt::extract_token(req, &self.settings.key)?;
+        let hash = openssl::sha::sha256(format!("{}\n{}", endpoint, credential).as_bytes());
+        Some(hash.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn get(&self, key: &str) -> std::option::Option<AuthorizerResult> {
+        let mut entries = self.entries.lock().unwrap(); // must never be poisoned
+        match entries.get(key) {
+            | Some((expiry, result)) if *expiry > Instant::now() => Some(result.clone()),
+            | Some(_) => {
+                entries.remove(key);
+                None
+            },
+            | None => None,
+        }
+    }
+
+    /// Stores the result for its configured TTL. Rejections are only stored
+    /// if `negative_ttl_sec` is set. Once `max_entries` is reached, expired
+    /// entries are purged and nothing is stored if there is still no room.
+    pub fn put(&self, key: String, result: &AuthorizerResult) {
+        let ttl = match result {
+            | Ok(_) => self.settings.ttl_sec,
+            | Err(_) => match self.settings.negative_ttl_sec {
+                | Some(v) => v,
+                | None => return,
+            },
+        };
+        let mut entries = self.entries.lock().unwrap(); // must never be poisoned
+        if entries.len() >= self.settings.max_entries {
+            let now = Instant::now();
+            entries.retain(|_, (expiry, _)| *expiry > now);
+            if entries.len() >= self.settings.max_entries {
+                return;
+            }
+        }
+        entries.insert(key, (Instant::now() + Duration::from_secs(ttl.into()), result.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(ttl_sec: u16, negative_ttl_sec: std::option::Option<u16>, max_entries: usize) -> AuthorizerCache {
+        AuthorizerCache::new(crate::config::AuthorizerCache {
+            key: crate::config::TokenSource::Header {
+                name: "authorization".to_owned(),
+            },
+            ttl_sec,
+            negative_ttl_sec,
+            max_entries,
+        })
+    }
+
+    fn allow(principal: &str) -> AuthorizerResult {
+        Ok(AuthorizerResponse {
+            context: None,
+            principal: Some(principal.to_owned()),
+            rooms: Vec::new(),
+        })
+    }
+
+    fn deny() -> AuthorizerResult {
+        Err(AuthorizerRejection::default())
+    }
+
+    #[test]
+    fn allows_and_denials_expire_separately() {
+        // a TTL of 0 expires the entry right away
+        let cache = cache(60, Some(0), 10);
+        cache.put("allow".to_owned(), &allow("user"));
+        cache.put("deny".to_owned(), &deny());
+        assert_eq!(cache.get("allow").unwrap().unwrap().principal.as_deref(), Some("user"));
+        assert!(cache.get("deny").is_none());
+
+        let cache = self::cache(0, Some(60), 10);
+        cache.put("allow".to_owned(), &allow("user"));
+        cache.put("deny".to_owned(), &deny());
+        assert!(cache.get("allow").is_none());
+        assert_eq!(cache.get("deny").unwrap().unwrap_err().status, 401);
+    }
+
+    #[test]
+    fn denials_are_not_cached_without_negative_ttl() {
+        let cache = cache(60, None, 10);
+        cache.put("deny".to_owned(), &deny());
+        assert!(cache.get("deny").is_none());
+    }
+
+    #[test]
+    fn nothing_is_stored_once_max_entries_is_reached() {
+        let cache = cache(60, None, 2);
+        cache.put("a".to_owned(), &allow("a"));
+        cache.put("b".to_owned(), &allow("b"));
+        cache.put("c".to_owned(), &allow("c"));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_none());
+    }
+
+    #[test]
+    fn expired_entries_are_evicted_once_max_entries_is_reached() {
+        let cache = cache(60, Some(0), 2);
+        cache.put("a".to_owned(), &deny());
+        cache.put("b".to_owned(), &deny());
+        cache.put("c".to_owned(), &allow("c"));
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+        assert!(cache.get("c").is_some());
+    }
+}
@@ -8,27 +8,338 @@ pub struct Config {
     pub routes: Routes,
 }
 
+impl Config {
+    /// Checks the configuration for inconsistent values that can not be
+    /// expressed by its structure.
+    pub fn validate(&self) -> std::result::Result<(), crate::error::ConfigError> {
+        // a mapping expiring with its renewal races the heartbeat
+        if self.redis.mapping_ttl_sec <= self.server.heartbeat_interval_sec {
+            return Err(crate::error::ConfigError::new(&format!(
+                "redis.mapping_ttl_sec ({}) must be greater than server.heartbeat_interval_sec ({})",
+                self.redis.mapping_ttl_sec, self.server.heartbeat_interval_sec
+            )));
+        }
+        if let Some(admin) = &self.server.admin {
+            if let AdminAuth::Mtls { .. } = admin.auth {
+                if admin.address.is_none() {
+                    return Err(crate::error::ConfigError::new(
+                        "server.admin.address is required for server.admin.auth.mtls",
+                    ));
+                }
+            }
+            if admin.address.as_ref() == Some(&self.server.address) {
+                return Err(crate::error::ConfigError::new(
+                    "server.admin.address must differ from server.address",
+                ));
+            }
+        }
+        validate_authorizers("routes", &self.routes.authorizer, &self.routes.jwt_authorizer)?;
+        for ep in self.routes.endpoints.iter() {
+            let name = format!("routes.endpoints[{}]", ep.path);
+            if let Some(v) = ep.heartbeat_interval_sec {
+                if self.redis.mapping_ttl_sec <= v {
+                    return Err(crate::error::ConfigError::new(&format!(
+                        "redis.mapping_ttl_sec ({}) must be greater than {}.heartbeat_interval_sec ({})",
+                        self.redis.mapping_ttl_sec, name, v
+                    )));
+                }
+            }
+            validate_authorizers(&name, &ep.authorizer, &ep.jwt_authorizer)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the comms mode of the given endpoint.
+    pub fn comms_for(&self, endpoint: &str) -> &CommsMode {
+        self.routes
+            .endpoint(endpoint)
+            .and_then(|v| v.comms.as_ref())
+            .unwrap_or(&self.server.comms)
+    }
+}
+
+fn validate_authorizers(
+    name: &str,
+    authorizer: &std::option::Option<Authorizer>,
+    jwt_authorizer: &std::option::Option<JwtAuthorizer>,
+) -> std::result::Result<(), crate::error::ConfigError> {
+    if let Some(jwt) = jwt_authorizer {
+        if authorizer.is_some() {
+            return Err(crate::error::ConfigError::new(&format!(
+                "{0}.authorizer and {0}.jwt_authorizer are mutually exclusive",
+                name
+            )));
+        }
+        if jwt.sources.is_empty() || jwt.algorithms.is_empty() {
+            return Err(crate::error::ConfigError::new(&format!(
+                "{}.jwt_authorizer requires at least one source and algorithm",
+                name
+            )));
+        }
+        if let Some(v) = jwt.algorithms.iter().find(|v| {
+            !matches!(
+                v,
+                jsonwebtoken::Algorithm::HS256 | jsonwebtoken::Algorithm::RS256 | jsonwebtoken::Algorithm::ES256
+            )
+        }) {
+            return Err(crate::error::ConfigError::new(&format!(
+                "{}.jwt_authorizer.algorithms does not support {:?}",
+                name, v
+            )));
+        }
+        if jwt.jwks_file.is_none() && jwt.keys.is_empty() {
+            return Err(crate::error::ConfigError::new(&format!(
+                "{}.jwt_authorizer requires a jwks_file or keys",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Routes {
-    pub endpoints: Vec<String>,
+    #[serde(deserialize_with = "EndpointRoute::deserialize_all")]
+    pub endpoints: Vec<EndpointRoute>,
     pub authorizer: std::option::Option<Authorizer>,
+    pub jwt_authorizer: std::option::Option<JwtAuthorizer>,
     pub connect: std::option::Option<ConnectRoute>,
     pub disconnect: std::option::Option<DisconnectRoute>,
 }
 
+impl Routes {
+    /// Looks up the endpoint with the given path.
+    pub fn endpoint(&self, path: &str) -> std::option::Option<&EndpointRoute> {
+        self.endpoints.iter().find(|v| v.path == path)
+    }
+
+    /// Returns the authorizer route of the given endpoint. Endpoints that
+    /// configure an authorizer or a JWT authorizer replace both of the shared
+    /// ones.
+    pub fn authorizer_for<'a>(&'a self, ep: &'a EndpointRoute) -> std::option::Option<&'a Authorizer> {
+        if ep.overrides_authorizer() {
+            ep.authorizer.as_ref()
+        } else {
+            self.authorizer.as_ref()
+        }
+    }
+
+    /// Returns the connect route of the given endpoint.
+    pub fn connect_for(&self, endpoint: &str) -> std::option::Option<&ConnectRoute> {
+        self.endpoint(endpoint)
+            .and_then(|v| v.connect.as_ref())
+            .or(self.connect.as_ref())
+    }
+
+    /// Returns the disconnect route of the given endpoint.
+    pub fn disconnect_for(&self, endpoint: &str) -> std::option::Option<&DisconnectRoute> {
+        self.endpoint(endpoint)
+            .and_then(|v| v.disconnect.as_ref())
+            .or(self.disconnect.as_ref())
+    }
+}
+
+/// A route clients can connect to on `/ws$path`.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct EndpointRoute {
+    pub path: String,
+    /// Subprotocols the endpoint supports. The first one requested by the
+    /// client is negotiated, none if empty.
+    #[serde(default)]
+    pub subprotocols: Vec<String>,
+
+    // overrides of the shared routes and server settings
+    pub authorizer: std::option::Option<Authorizer>,
+    pub jwt_authorizer: std::option::Option<JwtAuthorizer>,
+    pub connect: std::option::Option<ConnectRoute>,
+    pub disconnect: std::option::Option<DisconnectRoute>,
+    pub heartbeat_interval_sec: std::option::Option<u16>,
+    pub connection_timeout_sec: std::option::Option<u16>,
+    pub max_out_message_size: std::option::Option<usize>,
+    pub max_in_message_size: std::option::Option<usize>,
+    pub rate_limit: std::option::Option<RateLimit>,
+    pub admission: std::option::Option<Admission>,
+    pub comms: std::option::Option<CommsMode>,
+}
+
+impl EndpointRoute {
+    pub fn overrides_authorizer(&self) -> bool {
+        self.authorizer.is_some() || self.jwt_authorizer.is_some()
+    }
+
+    /// Reads the endpoints, each of which may also be given as its bare path.
+    fn deserialize_all<'de, D>(deserializer: D) -> std::result::Result<Vec<Self>, D::Error>
+    where D: serde::Deserializer<'de> {
+        let endpoints = <Vec<EndpointRouteForm> as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(endpoints
+            .into_iter()
+            .map(|v| match v {
+                | EndpointRouteForm::Path(path) => Self {
+                    path,
+                    ..Default::default()
+                },
+                | EndpointRouteForm::Route(v) => *v,
+            })
+            .collect())
+    }
+}
+
+/// The forms an endpoint can be configured in.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum EndpointRouteForm {
+    /// Only the path, as endpoints were configured before they had settings.
+    Path(String),
+    Route(Box<EndpointRoute>),
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Server {
     pub address: String,
     pub heartbeat_interval_sec: u16,
     pub stats_interval_sec: std::option::Option<u16>,
+    pub ack_timeout_sec: std::option::Option<u16>,
     pub connection_timeout_sec: u16,
     pub max_out_message_size: usize,
+    /// Falls back to `max_out_message_size` if missing.
+    #[serde(default)]
+    pub max_in_message_size: std::option::Option<usize>,
+    pub admin: std::option::Option<Admin>,
+    pub rate_limit: std::option::Option<RateLimit>,
+    pub admission: std::option::Option<Admission>,
+    pub shutdown: std::option::Option<Shutdown>,
+    pub tracing: std::option::Option<hydrogen_telemetry::Tracing>,
+    pub logging: std::option::Option<Logging>,
 
     pub comms: CommsMode,
 }
 
+impl Server {
+    /// Returns the size limit of messages received from clients.
+    pub fn in_message_size_limit(&self) -> usize {
+        self.max_in_message_size.unwrap_or(self.max_out_message_size)
+    }
+}
+
+/// Filtering and formatting of the log output.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Logging {
+    /// Default level and per event overrides, such as
+    /// `info,client_message=debug`. The `HYDROGEN_LOG` environment variable
+    /// takes precedence.
+    #[serde(default)]
+    pub filter: std::option::Option<String>,
+    #[serde(default)]
+    pub format: hydrogen_logger::Format,
+    /// Ratio of the messages of the given events that are logged.
+    #[serde(default)]
+    pub sampling: std::collections::HashMap<String, f64>,
+}
+
+/// Draining of the connections once the instance receives SIGTERM.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Shutdown {
+    /// Connections are closed at random points within this window.
+    pub drain_window_sec: u16,
+    /// Time to wait for the disconnects to finish after the window.
+    pub grace_sec: u16,
+    pub close_code: u16,
+    #[serde(default)]
+    pub close_reason: String,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            drain_window_sec: 0,
+            grace_sec: 5,
+            // service restart
+            close_code: 1012,
+            close_reason: String::new(),
+        }
+    }
+}
+
+/// Limits on the number of concurrent connections. Upgrade requests exceeding
+/// them are answered with 503.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Admission {
+    /// Connections of this instance.
+    pub max_instance_connections: std::option::Option<usize>,
+    /// Connections to the endpoint in the whole group.
+    pub max_endpoint_connections: std::option::Option<usize>,
+    /// Connections of a single principal in the whole group.
+    pub max_principal_connections: std::option::Option<usize>,
+    pub retry_after_sec: u16,
+}
+
+/// Token bucket limits on the messages clients send.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RateLimit {
+    pub connection: std::option::Option<RateLimitScope>,
+    /// Shared by all connections of a principal to the same endpoint on this
+    /// instance.
+    pub principal: std::option::Option<RateLimitScope>,
+    #[serde(default)]
+    pub on_violation: RateLimitViolation,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RateLimitScope {
+    pub messages: std::option::Option<TokenBucket>,
+    pub bytes: std::option::Option<TokenBucket>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenBucket {
+    pub rate_per_sec: u64,
+    /// Capacity of the bucket, defaults to `rate_per_sec`.
+    pub burst: std::option::Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitViolation {
+    #[default]
+    Drop,
+    Error,
+    Close,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Admin {
+    pub address: std::option::Option<String>,
+    pub auth: AdminAuth,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminAuth {
+    Bearer {
+        tokens: Vec<String>,
+    },
+    Hmac {
+        secret: String,
+        max_skew_sec: u16,
+    },
+    Mtls {
+        ca_cert: String,
+        cert: String,
+        key: String,
+        subjects: Vec<String>,
+    },
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum CommsMode {
@@ -41,12 +352,76 @@ pub enum CommsMode {
 pub struct Authorizer {
     pub endpoint: String,
     pub headers: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub forward_headers: Vec<String>,
+    pub cache: std::option::Option<AuthorizerCache>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AuthorizerCache {
+    pub key: TokenSource,
+    pub ttl_sec: u16,
+    pub negative_ttl_sec: std::option::Option<u16>,
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct JwtAuthorizer {
+    pub sources: Vec<TokenSource>,
+    pub algorithms: Vec<jsonwebtoken::Algorithm>,
+    pub jwks_file: std::option::Option<String>,
+    #[serde(default)]
+    pub keys: Vec<JwtKey>,
+    pub audience: std::option::Option<Vec<String>>,
+    pub issuer: std::option::Option<Vec<String>>,
+    #[serde(default)]
+    pub leeway_sec: u16,
+    #[serde(default)]
+    pub claims: Vec<String>,
+    pub principal_claim: std::option::Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenSource {
+    Header { name: String },
+    Query { name: String },
+    Cookie { name: String },
+    Subprotocol { prefix: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JwtKey {
+    Secret {
+        kid: std::option::Option<String>,
+        secret: String,
+    },
+    RsaPem {
+        kid: std::option::Option<String>,
+        file: String,
+    },
+    EcPem {
+        kid: std::option::Option<String>,
+        file: String,
+    },
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Redis {
     pub endpoint: String,
+    /// Defaults to 30 seconds, the expiry used before it was configurable.
+    #[serde(default = "Redis::default_mapping_ttl_sec")]
+    pub mapping_ttl_sec: u16,
+}
+
+impl Redis {
+    fn default_mapping_ttl_sec() -> u16 {
+        30
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -69,3 +444,84 @@ pub struct DisconnectRoute {
     pub endpoint: String,
     pub headers: std::collections::HashMap<String, String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+version: 0.1.0
+group_id: "g"
+server:
+  address: "0.0.0.0:8080"
+  heartbeat_interval_sec: 10
+  connection_timeout_sec: 31
+  max_out_message_size: 4096
+  comms: uni_server_to_client
+redis:
+  endpoint: "redis://127.0.0.1:6379"
+  mapping_ttl_sec: 40
+routes:
+  endpoints:
+    - path: "/"
+"#;
+
+    #[test]
+    fn max_in_message_size_defaults_to_max_out_message_size() {
+        let config = serde_yaml::from_str::<Config>(CONFIG).unwrap();
+        assert_eq!(config.server.in_message_size_limit(), 4096);
+
+        let config = serde_yaml::from_str::<Config>(&CONFIG.replace(
+            "  max_out_message_size: 4096\n",
+            "  max_out_message_size: 4096\n  max_in_message_size: 1024\n",
+        ))
+        .unwrap();
+        assert_eq!(config.server.in_message_size_limit(), 1024);
+    }
+
+    #[test]
+    fn endpoints_may_be_given_as_bare_paths() {
+        let config = serde_yaml::from_str::<Config>(&CONFIG.replace(
+            "    - path: \"/\"\n",
+            "    - \"/\"\n    - path: \"/telemetry\"\n      max_in_message_size: 1024\n",
+        ))
+        .unwrap();
+        let endpoints = &config.routes.endpoints;
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].path, "/");
+        assert!(endpoints[0].max_in_message_size.is_none());
+        assert_eq!(endpoints[1].path, "/telemetry");
+        assert_eq!(endpoints[1].max_in_message_size, Some(1024));
+    }
+
+    #[test]
+    fn mapping_ttl_sec_is_read() {
+        let config = serde_yaml::from_str::<Config>(CONFIG).unwrap();
+        assert_eq!(config.redis.mapping_ttl_sec, 40);
+    }
+
+    #[test]
+    fn mapping_ttl_sec_defaults_to_30_seconds() {
+        let config = serde_yaml::from_str::<Config>(&CONFIG.replace("  mapping_ttl_sec: 40\n", "")).unwrap();
+        assert_eq!(config.redis.mapping_ttl_sec, 30);
+    }
+
+    #[test]
+    fn mapping_ttl_sec_must_exceed_the_heartbeat_interval() {
+        let config = serde_yaml::from_str::<Config>(CONFIG).unwrap();
+        assert!(config.validate().is_ok());
+
+        let config = serde_yaml::from_str::<Config>(
+            &CONFIG.replace("  heartbeat_interval_sec: 10\n", "  heartbeat_interval_sec: 40\n"),
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let config = serde_yaml::from_str::<Config>(&CONFIG.replace(
+            "    - path: \"/\"\n",
+            "    - path: \"/\"\n      heartbeat_interval_sec: 40\n",
+        ))
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+}
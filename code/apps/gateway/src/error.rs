@@ -6,3 +6,6 @@ hydrogen_error::make_error!(ConnectRouteError);
 hydrogen_error::make_error!(DisconnectRouteError);
 hydrogen_error::make_error!(ConnectionNotFoundError);
 hydrogen_error::make_error!(ConfigError);
+hydrogen_error::make_error!(ReplyError);
+hydrogen_error::make_error!(JwtError);
+hydrogen_error::make_error!(PublishError);
@@ -1,13 +1,16 @@
 use actix::Addr;
 use actix_web::{
     self,
+    get,
+    http::StatusCode,
     post,
     web::{
+        self,
         Data,
         Path,
-        Payload,
     },
     Error,
+    HttpMessage,
     HttpRequest,
     HttpResponse,
 };
@@ -15,6 +18,7 @@ use futures::StreamExt;
 
 use crate::{
     config::Config,
+    messages::Payload,
     server::Server,
 };
 
@@ -28,20 +32,13 @@ pub struct BroadcastQueryParams {
 #[post("/connections/_broadcast")]
 pub async fn handle_broadcast_message(
     req: HttpRequest,
-    mut stream: Payload,
+    stream: web::Payload,
     srv: Data<Addr<Server>>,
     config: Data<Config>,
 ) -> Result<HttpResponse, Error> {
     let q_params = serde_qs::Config::new(4, false).deserialize_str::<BroadcastQueryParams>(&req.query_string())?;
-    let mut body = actix_web::web::BytesMut::new();
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > config.server.max_out_message_size {
-            return Err(actix_web::error::ErrorBadRequest("overflow"));
-        }
-        body.extend_from_slice(&chunk);
-    }
+    let body = read_body(stream, config.server.max_out_message_size).await?;
+    let message = make_payload(&req, body)?;
 
     match &q_params.endpoints {
         | Some(endpoints) => {
@@ -49,70 +46,161 @@ pub async fn handle_broadcast_message(
                 srv.do_send(crate::messages::BroadcastServerMessage::Endpoint {
                     endpoint: ep.to_owned(),
                     time: chrono::Utc::now().to_rfc3339(),
-                    message: String::from_utf8(body.to_vec()).unwrap(),
+                    message: message.clone(),
                 });
             }
         },
         | None => {
             srv.do_send(crate::messages::BroadcastServerMessage::All {
                 time: chrono::Utc::now().to_rfc3339(),
-                message: String::from_utf8(body.to_vec()).unwrap(),
+                message,
             });
         },
     }
     Ok(actix_web::HttpResponse::Ok().body(""))
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SendQueryParams {
+    pub ack: Option<bool>,
+}
+
 /// Endpoint for sending a message to the given connection, whether this
-/// instance is the owner of it or not is irrelevant as it is an async pub/sub
-/// process in the background.
+/// instance is the owner of it or not is irrelevant as it is delivered via
+/// pub/sub in the background. Responds with 404 if the connection is unknown.
+/// With `ack=true`, the response is delayed until the owning instance
+/// acknowledged the delivery (504 if it did not within `ack_timeout_sec`).
 #[post("/connections/{connection_id}/_send")]
 pub async fn handle_server_message(
-    mut stream: Payload,
+    req: HttpRequest,
+    stream: web::Payload,
     path: Path<String>,
     srv: Data<Addr<Server>>,
     config: Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let mut body = actix_web::web::BytesMut::new();
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > config.server.max_out_message_size {
-            return Err(actix_web::error::ErrorBadRequest("overflow"));
-        }
-        body.extend_from_slice(&chunk);
-    }
-    srv.do_send(crate::messages::ServerMessage {
-        connection: path.into_inner(),
-        time: chrono::Utc::now().to_rfc3339(),
-        message: String::from_utf8(body.to_vec()).unwrap(),
-    });
-    Ok(actix_web::HttpResponse::Ok().body(""))
+    let q_params = serde_qs::Config::new(4, false).deserialize_str::<SendQueryParams>(req.query_string())?;
+    let body = read_body(stream, config.server.max_out_message_size).await?;
+    let ack = match q_params.ack {
+        | Some(true) => match config.server.ack_timeout_sec {
+            | Some(v) => Some(std::time::Duration::from_secs(v.into())),
+            | None => return Err(actix_web::error::ErrorBadRequest("acknowledgements are disabled")),
+        },
+        | _ => None,
+    };
+
+    let res = srv
+        .send(crate::messages::ServerMessage {
+            connection: path.into_inner(),
+            time: chrono::Utc::now().to_rfc3339(),
+            message: make_payload(&req, body)?,
+            ack,
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
 }
 
 /// Endpoint for forcing the disconnect of a given connection, whether this
 /// instance is the owner of it or not is irrelevant as it is an async pub/sub
-/// process in the background.
+/// process in the background. Responds with 404 if the connection is unknown.
 #[post("/connections/{connection_id}/_disconnect")]
 pub async fn handle_disconnect(
-    mut stream: Payload,
+    stream: web::Payload,
     path: Path<String>,
     srv: Data<Addr<Server>>,
     config: Data<Config>,
 ) -> Result<HttpResponse, Error> {
+    let body = read_body(stream, config.server.max_out_message_size).await?;
+    let res = srv
+        .send(crate::messages::ServerDisconnect {
+            connection: path.into_inner(),
+            time: chrono::Utc::now().to_rfc3339(),
+            reason: String::from_utf8(body.to_vec()).map_err(actix_web::error::ErrorBadRequest)?,
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ListQueryParams {
+    pub endpoint: Option<String>,
+    pub cursor: Option<u64>,
+    pub count: Option<usize>,
+}
+
+/// Endpoint for looking up the details of a given connection anywhere in the
+/// group. Responds with 404 if the connection is unknown.
+#[get("/connections/{connection_id}")]
+pub async fn handle_get_connection(path: Path<String>, srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let res = srv
+        .send(crate::messages::GetConnection {
+            connection: path.into_inner(),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    match res {
+        | Ok(v) => Ok(HttpResponse::Ok().json(v)),
+        | Err(code) => Ok(make_response(Err(code))),
+    }
+}
+
+/// Endpoint for listing the connections of the group, optionally filtered by
+/// endpoint. The listing is paginated, the `cursor` of a page has to be passed
+/// on to request the next one. Pages may be empty while a cursor is returned.
+#[get("/connections")]
+pub async fn handle_list_connections(req: HttpRequest, srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let q_params = serde_qs::Config::new(4, false).deserialize_str::<ListQueryParams>(req.query_string())?;
+    let res = srv
+        .send(crate::messages::ListConnections {
+            endpoint: q_params.endpoint,
+            cursor: q_params.cursor.unwrap_or(0),
+            count: q_params.count.unwrap_or(100),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    match res {
+        | Ok(v) => Ok(HttpResponse::Ok().json(v)),
+        | Err(code) => Ok(make_response(Err(code))),
+    }
+}
+
+/// Reads the request body into memory, rejecting it once it exceeds
+/// `max_size`.
+pub async fn read_body<S>(mut stream: S, max_size: usize) -> Result<actix_web::web::BytesMut, Error>
+where S: futures::Stream<Item=Result<actix_web::web::Bytes, actix_web::error::PayloadError>>+Unpin {
     let mut body = actix_web::web::BytesMut::new();
     while let Some(chunk) = stream.next().await {
         let chunk = chunk?;
         // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > config.server.max_out_message_size {
+        if (body.len() + chunk.len()) > max_size {
             return Err(actix_web::error::ErrorBadRequest("overflow"));
         }
         body.extend_from_slice(&chunk);
     }
-    srv.do_send(crate::messages::ServerDisconnect {
-        connection: path.into_inner(),
-        time: chrono::Utc::now().to_rfc3339(),
-        reason: String::from_utf8(body.to_vec()).unwrap(),
-    });
-    Ok(actix_web::HttpResponse::Ok().body(""))
+    Ok(body)
+}
+
+/// Interprets the request body as binary data if it is marked as
+/// `application/octet-stream` and as UTF-8 text otherwise.
+pub fn make_payload(req: &HttpRequest, body: actix_web::web::BytesMut) -> Result<Payload, Error> {
+    match req.content_type() {
+        | "application/octet-stream" => Ok(Payload::Binary(body.to_vec())),
+        | _ => Ok(Payload::Text(
+            String::from_utf8(body.to_vec()).map_err(actix_web::error::ErrorBadRequest)?,
+        )),
+    }
+}
+
+/// Maps the result of a server actor message to an empty response with the
+/// corresponding status code.
+pub fn make_response(res: std::result::Result<(), u16>) -> HttpResponse {
+    match res {
+        | Ok(_) => HttpResponse::Ok().body(""),
+        | Err(code) => {
+            HttpResponse::build(StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)).body("")
+        },
+    }
 }
@@ -1,18 +1,99 @@
+use std::collections::BTreeMap;
+
+use actix::Addr;
 use actix_web::{
     get,
+    web::Data,
     Error,
     HttpResponse,
 };
 
+use crate::{
+    messages::{
+        Check,
+        CheckDependencies,
+        CheckStatus,
+        IsAlive,
+        IsSubscribed,
+    },
+    server::{
+        Server,
+        PROBE_TIMEOUT,
+    },
+    shutdown::Draining,
+};
+
 #[derive(serde::Serialize)]
 struct Health {
     status: String,
 }
 
-/// Healthcheck endpoint returning static data.
+/// The response of the liveness and readiness probes.
+#[derive(serde::Serialize)]
+struct Probe {
+    status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    draining: std::option::Option<bool>,
+    checks: BTreeMap<String, Check>,
+}
+
+impl Probe {
+    /// Answers with `code 200` if every check is up, `code 503` otherwise.
+    fn respond(draining: std::option::Option<bool>, checks: BTreeMap<String, Check>) -> HttpResponse {
+        let up = draining != Some(true) && checks.values().all(|v| v.status == CheckStatus::Up);
+        let probe = Probe {
+            status: if up { CheckStatus::Up } else { CheckStatus::Down },
+            draining,
+            checks,
+        };
+        match up {
+            | true => HttpResponse::Ok().json(probe),
+            | false => HttpResponse::ServiceUnavailable().json(probe),
+        }
+    }
+}
+
+/// Healthcheck endpoint reporting `degraded` while the instance is not
+/// subscribed to redis and can not receive server messages.
 #[get("/health")]
-pub async fn handler() -> Result<HttpResponse, Error> {
+pub async fn handler(srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let status = match srv.send(IsSubscribed).await {
+        | Ok(true) => "up",
+        | _ => "degraded",
+    };
     Ok(actix_web::HttpResponse::Ok().json(Health {
-        status: "up".to_owned(),
+        status: status.to_owned(),
     }))
 }
+
+/// Liveness probe failing once the server stops processing messages or the
+/// redis subscriber thread is gone, neither of which recovers.
+#[get("/health/live")]
+pub async fn liveness(srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let mut checks = BTreeMap::new();
+    match srv.send(IsAlive).timeout(PROBE_TIMEOUT).await {
+        | Ok(alive) => {
+            checks.insert("server".to_owned(), Check::up());
+            checks.insert("redis_subscriber".to_owned(), match alive {
+                | true => Check::up(),
+                | false => Check::down("stopped"),
+            });
+        },
+        | Err(e) => {
+            checks.insert("server".to_owned(), Check::down(&e.to_string()));
+        },
+    }
+    Ok(Probe::respond(None, checks))
+}
+
+/// Readiness probe failing while redis, the redis subscription or the
+/// JetStream of a `bidi` route is unavailable and once the instance drains.
+#[get("/health/ready")]
+pub async fn readiness(srv: Data<Addr<Server>>, draining: Data<Draining>) -> Result<HttpResponse, Error> {
+    // every dependency is checked concurrently within the probe timeout
+    let checks = match srv.send(CheckDependencies).timeout(PROBE_TIMEOUT * 2).await {
+        | Ok(v) => v,
+        | Err(e) => BTreeMap::from([("server".to_owned(), Check::down(&e.to_string()))]),
+    };
+    Ok(Probe::respond(Some(draining.is_set()), checks))
+}
@@ -0,0 +1,70 @@
+use actix::Addr;
+use actix_web::{
+    self,
+    post,
+    web::{
+        self,
+        Data,
+        Path,
+    },
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::{
+    config::Config,
+    handlers::connection::{
+        make_payload,
+        make_response,
+        read_body,
+    },
+    server::Server,
+};
+
+/// Endpoint for sending a message to all connections of the given principal,
+/// regardless of the instances holding them. Responds with 404 if the
+/// principal has no connections.
+#[post("/principals/{principal_id}/_send")]
+pub async fn handle_principal_message(
+    req: HttpRequest,
+    stream: web::Payload,
+    path: Path<String>,
+    srv: Data<Addr<Server>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let body = read_body(stream, config.server.max_out_message_size).await?;
+
+    let res = srv
+        .send(crate::messages::PrincipalServerMessage {
+            principal: path.into_inner(),
+            time: chrono::Utc::now().to_rfc3339(),
+            message: make_payload(&req, body)?,
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
+}
+
+/// Endpoint for forcing the disconnect of all connections of the given
+/// principal. The request body is sent to the clients as reason of the close
+/// frame. Responds with 404 if the principal has no connections.
+#[post("/principals/{principal_id}/_disconnect")]
+pub async fn handle_principal_disconnect(
+    stream: web::Payload,
+    path: Path<String>,
+    srv: Data<Addr<Server>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let body = read_body(stream, config.server.max_out_message_size).await?;
+
+    let res = srv
+        .send(crate::messages::PrincipalDisconnect {
+            principal: path.into_inner(),
+            time: chrono::Utc::now().to_rfc3339(),
+            reason: String::from_utf8(body.to_vec()).map_err(actix_web::error::ErrorBadRequest)?,
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
+}
@@ -0,0 +1,94 @@
+use actix::Addr;
+use actix_web::{
+    self,
+    delete,
+    get,
+    post,
+    put,
+    web::{
+        self,
+        Data,
+        Path,
+    },
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::{
+    config::Config,
+    handlers::connection::{
+        make_payload,
+        make_response,
+        read_body,
+    },
+    server::Server,
+};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RoomMembers {
+    pub connections: Vec<String>,
+}
+
+/// Endpoint for sending a message to all members of the given room. The
+/// message is only delivered to the instances that hold members.
+#[post("/rooms/{room}/_send")]
+pub async fn handle_room_message(
+    req: HttpRequest,
+    stream: web::Payload,
+    path: Path<String>,
+    srv: Data<Addr<Server>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let body = read_body(stream, config.server.max_out_message_size).await?;
+
+    let res = srv
+        .send(crate::messages::RoomServerMessage {
+            room: path.into_inner(),
+            time: chrono::Utc::now().to_rfc3339(),
+            message: make_payload(&req, body)?,
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
+}
+
+/// Endpoint for listing the connections that are members of the given room.
+#[get("/rooms/{room}/connections")]
+pub async fn handle_list_members(path: Path<String>, srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let res = srv
+        .send(crate::messages::RoomMembers {
+            room: path.into_inner(),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    match res {
+        | Ok(connections) => Ok(HttpResponse::Ok().json(RoomMembers { connections })),
+        | Err(code) => Ok(make_response(Err(code))),
+    }
+}
+
+/// Endpoint for adding the given connection to the given room. Responds with
+/// 404 if the connection is unknown.
+#[put("/rooms/{room}/connections/{connection_id}")]
+pub async fn handle_join(path: Path<(String, String)>, srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let (room, connection) = path.into_inner();
+    let res = srv
+        .send(crate::messages::JoinRoom { room, connection })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
+}
+
+/// Endpoint for removing the given connection from the given room. Responds
+/// with 404 if the connection is unknown.
+#[delete("/rooms/{room}/connections/{connection_id}")]
+pub async fn handle_leave(path: Path<(String, String)>, srv: Data<Addr<Server>>) -> Result<HttpResponse, Error> {
+    let (room, connection) = path.into_inner();
+    let res = srv
+        .send(crate::messages::LeaveRoom { room, connection })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(make_response(res))
+}
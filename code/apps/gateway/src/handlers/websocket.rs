@@ -1,5 +1,6 @@
 use actix::Addr;
 use actix_web::{
+    http::StatusCode,
     web::{
         Data,
         Payload,
@@ -9,9 +10,11 @@ use actix_web::{
     HttpResponse,
 };
 use actix_web_actors::ws;
+use opentelemetry::trace::TraceContextExt;
 
 use crate::{
     config::Config,
+    routes::AuthorizerRejection,
     server::Server,
     types::{
         Endpoint,
@@ -24,9 +27,10 @@ use crate::{
 /// Handler for initiating connection between client and server. This function
 /// is only invoked at the first stage of initiation and a connection is
 /// established from there. This function will also invoke the authorizer route
-/// if present to determine whether the connection may or may not be
-/// established. It will also enrich the context of the connection with
-/// the context that is returned by the authorizer in it's response.
+/// (or the built-in JWT authorizer) if present to determine whether the
+/// connection may or may not be established. It will also enrich the context of
+/// the connection with the context that is returned by the authorizer in it's
+/// response.
 pub async fn handler(
     req: HttpRequest,
     stream: Payload,
@@ -34,72 +38,349 @@ pub async fn handler(
     config: Data<Config>,
     instance: Data<InstanceID>,
     group: Data<GroupID>,
-    endpoint: Data<Endpoint>,
+    route: Data<crate::config::EndpointRoute>,
 ) -> Result<HttpResponse, Error> {
+    let endpoint: &Endpoint = &route.path;
     let safecall_auth =
         |conn_id: &str,
-         auth_route: &std::option::Option<crate::config::Authorizer>|
+         auth_route: std::option::Option<&crate::config::Authorizer>,
+         trace: &opentelemetry::Context|
          -> Result<std::option::Option<crate::routes::AuthorizerResponse>, Box<dyn std::error::Error>> {
-            match auth_route {
-                | Some(c) => Ok(Some(invoke_authorizer_route(
-                    &instance,
-                    &group,
-                    &c.endpoint,
-                    &c.headers,
-                    conn_id,
-                )?)),
-                | None => Ok(None),
+            if let Some(jwt) = req.app_data::<Data<crate::jwt::JwtVerifier>>() {
+                return Ok(Some(jwt.authorize(&req)?));
             }
+            let route = match auth_route {
+                | Some(c) => c,
+                | None => return Ok(None),
+            };
+
+            let cache = req.app_data::<Data<crate::cache::AuthorizerCache>>();
+            let key = cache.and_then(|v| v.key(&req, endpoint));
+            if let (Some(cache), Some(key)) = (cache, &key) {
+                if let Some(v) = cache.get(key) {
+                    return Ok(Some(v?));
+                }
+            }
+            let request = make_authorizer_request(&req, route, &instance, &group, endpoint, conn_id);
+            let result = match invoke_authorizer_route(&instance, route, &request, trace) {
+                | Ok(v) => Ok(v),
+                // only definite answers of the authorizer are cached
+                | Err(e) => Err(*e.downcast::<AuthorizerRejection>()?),
+            };
+            if let (Some(cache), Some(key)) = (cache, key) {
+                cache.put(key, &result);
+            }
+            Ok(Some(result?))
         };
 
+    // instances that shut down do not accept new connections
+    if req
+        .app_data::<Data<crate::shutdown::Draining>>()
+        .is_some_and(|v| v.is_set())
+    {
+        crate::metrics::UPGRADES
+            .with_label_values(&[endpoint, "draining"])
+            .inc();
+        return Ok(HttpResponse::ServiceUnavailable().finish());
+    }
+
+    let max_in_message_size = route
+        .max_in_message_size
+        .unwrap_or(config.server.in_message_size_limit());
+    let subprotocol = negotiate_subprotocol(&req, &route.subprotocols);
     let ws_id = WsConn::claim_id();
-    match safecall_auth(&ws_id, &config.routes.authorizer) {
+    let upgrade = hydrogen_telemetry::start(
+        "ws.upgrade",
+        opentelemetry::trace::SpanKind::Server,
+        &hydrogen_telemetry::extract(
+            req.headers()
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?))),
+        ),
+        vec![
+            opentelemetry::KeyValue::new("hydrogen.connection_id", ws_id.clone()),
+            opentelemetry::KeyValue::new("hydrogen.endpoint", endpoint.clone()),
+        ],
+    );
+    match safecall_auth(&ws_id, config.routes.authorizer_for(&route), &upgrade) {
         | Ok(ar) => {
+            let context = match ar {
+                | Some(ar) => crate::ws::WsConnContext {
+                    authorizer: ar.context,
+                    principal: ar.principal,
+                    rooms: ar.rooms,
+                    subprotocol: subprotocol.clone(),
+                    trace: upgrade.clone(),
+                },
+                | None => crate::ws::WsConnContext {
+                    authorizer: None,
+                    principal: None,
+                    rooms: Vec::new(),
+                    subprotocol: subprotocol.clone(),
+                    trace: upgrade.clone(),
+                },
+            };
+            let limits = route
+                .rate_limit
+                .as_ref()
+                .or(config.server.rate_limit.as_ref())
+                .map(|settings| {
+                    let principals = req
+                        .app_data::<Data<crate::limits::PrincipalLimiters>>()
+                        .map(|v| v.clone().into_inner())
+                        .unwrap_or_default();
+                    crate::limits::ConnectionLimits::new(settings, endpoint, context.principal.as_deref(), principals)
+                });
+            let admission = match route.admission.as_ref().or(config.server.admission.as_ref()) {
+                | Some(settings) => match admit(&req, &srv, settings, &ws_id, endpoint, &context.principal).await {
+                    | Ok(slot) => Some(slot),
+                    | Err(reason) => {
+                        crate::logger::LogMessage::now(&instance.to_string(), crate::logger::Data::Event {
+                            data: crate::logger::Event::AdmissionRejected {
+                                connection: &ws_id,
+                                reason,
+                            },
+                        });
+                        crate::metrics::UPGRADES
+                            .with_label_values(&[endpoint, "admission"])
+                            .inc();
+                        upgrade
+                            .span()
+                            .set_attribute(opentelemetry::KeyValue::new("hydrogen.upgrade.result", "admission"));
+                        return Ok(HttpResponse::ServiceUnavailable()
+                            .insert_header((
+                                actix_web::http::header::RETRY_AFTER,
+                                settings.retry_after_sec.to_string(),
+                            ))
+                            .finish());
+                    },
+                },
+                | None => None,
+            };
+            let release = admission.as_ref().map(|_| crate::messages::Release {
+                connection: ws_id.clone(),
+                endpoint: endpoint.to_owned(),
+                principal: context.principal.clone(),
+            });
             let ws = WsConn::new(
                 ws_id,
+                instance.as_ref().to_owned(),
                 group.as_ref().to_owned(),
-                endpoint.get_ref().to_owned(),
+                endpoint.to_owned(),
                 srv.get_ref().clone(),
-                crate::ws::WsConnContext {
-                    authorizer: match ar {
-                        | Some(ar) => ar.context,
-                        | None => None,
-                    },
+                context,
+                crate::ws::WsConnSettings {
+                    heartbeat_interval: std::time::Duration::from_secs(
+                        route
+                            .heartbeat_interval_sec
+                            .unwrap_or(config.server.heartbeat_interval_sec)
+                            .into(),
+                    ),
+                    timeout: std::time::Duration::from_secs(
+                        route
+                            .connection_timeout_sec
+                            .unwrap_or(config.server.connection_timeout_sec)
+                            .into(),
+                    ),
+                    max_in_message_size,
+                    max_out_message_size: route.max_out_message_size.unwrap_or(config.server.max_out_message_size),
+                    limits,
+                    admission,
+                },
+            )
+            .read(crate::ws::Frames::new(stream, max_in_message_size));
+            let protocols = subprotocol.iter().map(|v| v.as_str()).collect::<Vec<_>>();
+            // the connection decodes the frames of the client itself
+            let resp = match ws::WsResponseBuilder::new(ws, &req, futures::stream::pending())
+                .protocols(&protocols)
+                .start()
+            {
+                | Ok(v) => v,
+                | Err(e) => {
+                    // the connection was counted but will never disconnect
+                    if let Some(release) = release {
+                        srv.do_send(release);
+                    }
+                    return Err(e);
                 },
-                std::time::Duration::from_secs(config.server.heartbeat_interval_sec.into()),
-                std::time::Duration::from_secs(config.server.connection_timeout_sec.into()),
-            );
-            let resp = ws::start(ws, &req, stream)?;
+            };
+            crate::metrics::UPGRADES
+                .with_label_values(&[endpoint, "accepted"])
+                .inc();
+            // the context lives on with the connection as parent of its routes
+            upgrade
+                .span()
+                .set_attribute(opentelemetry::KeyValue::new("hydrogen.upgrade.result", "accepted"));
+            upgrade.span().end();
             Ok(resp)
         },
         | Err(e) => {
             crate::logger::LogMessage::now(&instance.to_string(), crate::logger::Data::Event {
                 data: crate::logger::Event::Error { err: &e.to_string() },
             });
-            Err(actix_web::error::ErrorUnauthorized(e))
+            crate::metrics::UPGRADES
+                .with_label_values(&[endpoint, "unauthorized"])
+                .inc();
+            upgrade
+                .span()
+                .set_attribute(opentelemetry::KeyValue::new("hydrogen.upgrade.result", "unauthorized"));
+            hydrogen_telemetry::record_error(&upgrade, e.as_ref());
+            match e.downcast_ref::<AuthorizerRejection>() {
+                | Some(rejection) => Ok(make_rejection_response(rejection)),
+                | None => Err(actix_web::error::ErrorUnauthorized(e)),
+            }
         },
     }
 }
 
-fn invoke_authorizer_route(
+/// Builds the response the authorizer asked for when rejecting a connection.
+/// Only client and server error codes are allowed, anything else is replaced
+/// by 401.
+fn make_rejection_response(rejection: &AuthorizerRejection) -> HttpResponse {
+    let status = match StatusCode::from_u16(rejection.status) {
+        | Ok(v) if v.is_client_error() || v.is_server_error() => v,
+        | _ => StatusCode::UNAUTHORIZED,
+    };
+    let mut resp = HttpResponse::build(status);
+    for (k, v) in rejection.headers.iter() {
+        resp.insert_header((k.as_str(), v.as_str()));
+    }
+    resp.body(rejection.body.clone())
+}
+
+/// Admits the connection against the limits of this instance and of the
+/// group. Returns the reason if any of them is reached.
+async fn admit(
+    req: &HttpRequest,
+    srv: &Addr<Server>,
+    settings: &crate::config::Admission,
+    conn_id: &str,
+    endpoint: &str,
+    principal: &std::option::Option<String>,
+) -> std::result::Result<crate::admission::AdmissionSlot, &'static str> {
+    let slot = req
+        .app_data::<Data<crate::admission::InstanceConnections>>()
+        .map(|v| v.clone().into_inner())
+        .unwrap_or_default()
+        .try_acquire(settings.max_instance_connections)
+        .ok_or("instance connection limit reached")?;
+    match srv
+        .send(crate::messages::Admit {
+            connection: conn_id.to_owned(),
+            endpoint: endpoint.to_owned(),
+            principal: principal.clone(),
+            max_endpoint_connections: settings.max_endpoint_connections,
+            max_principal_connections: settings.max_principal_connections,
+        })
+        .await
+    {
+        | Ok(Ok(true)) => Ok(slot),
+        | Ok(Ok(false)) => Err("group connection limit reached"),
+        | _ => Err("admission failed"),
+    }
+}
+
+/// Selects the first subprotocol requested by the client through
+/// `Sec-WebSocket-Protocol` that the endpoint supports.
+fn negotiate_subprotocol(req: &HttpRequest, supported: &[String]) -> std::option::Option<String> {
+    requested_subprotocols(req).into_iter().find(|v| supported.contains(v))
+}
+
+/// Lists the subprotocols requested by the client in order of preference.
+fn requested_subprotocols(req: &HttpRequest) -> Vec<String> {
+    req.headers()
+        .get_all(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Describes the upgrade request of the client for the authorizer. Only the
+/// headers in the `forward_headers` allow-list of the route are passed on.
+fn make_authorizer_request(
+    req: &HttpRequest,
+    route: &crate::config::Authorizer,
     instance: &str,
     group: &str,
     endpoint: &str,
-    headers: &std::collections::HashMap<String, String>,
     conn_id: &str,
-) -> std::result::Result<crate::routes::AuthorizerResponse, Box<dyn std::error::Error>> {
-    let mut auth_req = ureq::post(endpoint);
-    for (k, v) in headers.iter() {
-        auth_req = auth_req.set(k, v);
-    }
-    let resp = auth_req.send_string(&serde_json::to_string(&crate::routes::AuthorizerRequest {
+) -> crate::routes::AuthorizerRequest {
+    crate::routes::AuthorizerRequest {
         instance_id: instance.to_owned(),
         group_id: group.to_owned(),
         connection_id: conn_id.to_owned(),
         endpoint: endpoint.to_owned(),
+        path: req.path().to_owned(),
         time: chrono::Utc::now().to_rfc3339(),
-        headers: headers.iter().map(|v| (v.0.to_owned(), v.1.to_owned())).collect(),
-    })?)?;
+        headers: req
+            .headers()
+            .iter()
+            .filter(|(k, _)| route.forward_headers.iter().any(|v| v.eq_ignore_ascii_case(k.as_str())))
+            .filter_map(|(k, v)| Some((k.as_str().to_owned(), v.to_str().ok()?.to_owned())))
+            .collect(),
+        query: req.query_string().to_owned(),
+        cookies: match req.cookies() {
+            | Ok(v) => v.iter().map(|c| (c.name().to_owned(), c.value().to_owned())).collect(),
+            | Err(_) => std::collections::HashMap::new(),
+        },
+        remote_addr: req.peer_addr().map(|v| v.to_string()),
+        subprotocols: requested_subprotocols(req),
+    }
+}
+
+fn invoke_authorizer_route(
+    instance: &str,
+    route: &crate::config::Authorizer,
+    request: &crate::routes::AuthorizerRequest,
+    trace: &opentelemetry::Context,
+) -> std::result::Result<crate::routes::AuthorizerResponse, Box<dyn std::error::Error>> {
+    let cx = hydrogen_telemetry::start("authorizer", opentelemetry::trace::SpanKind::Client, trace, vec![]);
+    let mut auth_req = ureq::post(&route.endpoint);
+    for (k, v) in route.headers.iter().chain(hydrogen_telemetry::inject(&cx).iter()) {
+        auth_req = auth_req.set(k, v);
+    }
+    let started = std::time::Instant::now();
+    let res = auth_req.send_string(&serde_json::to_string(request)?);
+    let status = match &res {
+        | Ok(v) => Some(v.status()),
+        | Err(ureq::Error::Status(code, _)) => Some(*code),
+        | Err(e) => {
+            hydrogen_telemetry::record_error(&cx, e);
+            None
+        },
+    };
+    crate::metrics::observe_route("authorizer", started, status);
+    if let Some(v) = status {
+        hydrogen_telemetry::record_status(&cx, v);
+    }
+    let resp = match res {
+        | Ok(v) => v,
+        // client errors are definite rejections, the body of which is optional
+        | Err(ureq::Error::Status(code, resp)) => {
+            crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
+                data: crate::logger::Event::AuthRouteResponse {
+                    connection: &request.connection_id,
+                    response: code,
+                },
+            });
+            if !(400..500).contains(&code) {
+                return Err(Box::new(crate::error::AuthorizerRouteError::new(&format!(
+                    "authorizer route error code {}",
+                    code
+                ))));
+            }
+            let headers = resp
+                .headers_names()
+                .into_iter()
+                .filter_map(|k| Some((k.clone(), resp.header(&k)?.to_owned())))
+                .collect();
+            let body = resp.into_string().unwrap_or_default();
+            return Err(Box::new(AuthorizerRejection::from_response(code, headers, &body)));
+        },
+        | Err(e) => return Err(Box::new(e)),
+    };
     let resp_status = resp.status();
 
     // TODO(AWE): instrumentation has shown that the following statement takes
@@ -110,7 +391,7 @@ fn invoke_authorizer_route(
     let resp_parsed = serde_json::from_str::<crate::routes::AuthorizerResponse>(&respstr)?;
     crate::logger::LogMessage::now(&instance.to_string(), crate::logger::Data::Event {
         data: crate::logger::Event::AuthRouteResponse {
-            connection: &conn_id.to_string(),
+            connection: &request.connection_id,
             response: resp_status,
         },
     });
@@ -0,0 +1,323 @@
+use actix_web::HttpRequest;
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters,
+        JwkSet,
+    },
+    Algorithm,
+    DecodingKey,
+    Validation,
+};
+
+use crate::{
+    config::{
+        JwtAuthorizer,
+        JwtKey,
+        TokenSource,
+    },
+    error::JwtError,
+};
+
+type Claims = std::collections::HashMap<String, serde_json::Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyKind {
+    Hmac,
+    Rsa,
+    Ec,
+}
+
+impl KeyKind {
+    fn of(alg: Algorithm) -> std::option::Option<Self> {
+        match alg {
+            | Algorithm::HS256 => Some(KeyKind::Hmac),
+            | Algorithm::RS256 => Some(KeyKind::Rsa),
+            | Algorithm::ES256 => Some(KeyKind::Ec),
+            | _ => None,
+        }
+    }
+}
+
+struct VerifierKey {
+    kid: std::option::Option<String>,
+    kind: KeyKind,
+    key: DecodingKey,
+}
+
+/// Built-in authorizer that validates JWTs presented on the upgrade request
+/// against locally configured keys instead of invoking an authorizer route.
+pub struct JwtVerifier {
+    config: JwtAuthorizer,
+    keys: Vec<VerifierKey>,
+}
+
+impl JwtVerifier {
+    /// Loads the configured keys, fails if any of them can not be read.
+    pub fn new(config: &JwtAuthorizer) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        let mut keys = Vec::new();
+        if let Some(path) = &config.jwks_file {
+            let jwks = serde_json::from_slice::<JwkSet>(&std::fs::read(path)?)?;
+            for jwk in jwks.keys.iter() {
+                let kind = match jwk.algorithm {
+                    | AlgorithmParameters::OctetKey(_) => KeyKind::Hmac,
+                    | AlgorithmParameters::RSA(_) => KeyKind::Rsa,
+                    | AlgorithmParameters::EllipticCurve(_) => KeyKind::Ec,
+                    // unsupported key types are skipped
+                    | AlgorithmParameters::OctetKeyPair(_) => continue,
+                };
+                keys.push(VerifierKey {
+                    kid: jwk.common.key_id.clone(),
+                    kind,
+                    key: DecodingKey::from_jwk(jwk)?,
+                });
+            }
+        }
+        for key in config.keys.iter() {
+            keys.push(match key {
+                | JwtKey::Secret { kid, secret } => VerifierKey {
+                    kid: kid.clone(),
+                    kind: KeyKind::Hmac,
+                    key: DecodingKey::from_secret(secret.as_bytes()),
+                },
+                | JwtKey::RsaPem { kid, file } => VerifierKey {
+                    kid: kid.clone(),
+                    kind: KeyKind::Rsa,
+                    key: DecodingKey::from_rsa_pem(&std::fs::read(file)?)?,
+                },
+                | JwtKey::EcPem { kid, file } => VerifierKey {
+                    kid: kid.clone(),
+                    kind: KeyKind::Ec,
+                    key: DecodingKey::from_ec_pem(&std::fs::read(file)?)?,
+                },
+            });
+        }
+        if keys.is_empty() {
+            return Err(Box::new(JwtError::new("no usable keys configured")));
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            keys,
+        })
+    }
+
+    /// Validates the token of the upgrade request and maps its claims into
+    /// the same response an authorizer route would return.
+    pub fn authorize(&self, req: &HttpRequest) -> std::result::Result<crate::routes::AuthorizerResponse, JwtError> {
+        let token = self
+            .config
+            .sources
+            .iter()
+            .find_map(|source| extract_token(req, source))
+            .ok_or_else(|| JwtError::new("no token presented"))?;
+        let claims = self.verify(&token)?;
+
+        let context = self
+            .config
+            .claims
+            .iter()
+            .filter_map(|name| Some((name.clone(), claims.get(name)?.clone())))
+            .collect::<crate::ws::WsConnContextMap>();
+        let principal = match self.config.principal_claim.as_ref().and_then(|v| claims.get(v)) {
+            | Some(serde_json::Value::String(v)) => Some(v.clone()),
+            | Some(v) => Some(v.to_string()),
+            | None => None,
+        };
+        Ok(crate::routes::AuthorizerResponse {
+            context: Some(context),
+            principal,
+            rooms: Vec::new(),
+        })
+    }
+
+    /// Verifies the signature of the token with every key that matches its
+    /// algorithm (and key id, if present) and checks the registered claims.
+    fn verify(&self, token: &str) -> std::result::Result<Claims, JwtError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| JwtError::new(&e.to_string()))?;
+        if !self.config.algorithms.contains(&header.alg) {
+            return Err(JwtError::new(&format!("algorithm {:?} is not allowed", header.alg)));
+        }
+        let kind = KeyKind::of(header.alg).ok_or_else(|| JwtError::new("algorithm is not supported"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway_sec.into();
+        validation.validate_nbf = true;
+        validation.validate_aud = self.config.audience.is_some();
+        if let Some(audience) = &self.config.audience {
+            validation.set_audience(audience);
+        }
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(issuer);
+        }
+
+        let mut err = JwtError::new("no matching key");
+        for key in self
+            .keys
+            .iter()
+            .filter(|v| v.kind == kind)
+            .filter(|v| header.kid.is_none() || v.kid.is_none() || v.kid == header.kid)
+        {
+            match jsonwebtoken::decode::<Claims>(token, &key.key, &validation) {
+                | Ok(v) => return Ok(v.claims),
+                | Err(e) => err = JwtError::new(&e.to_string()),
+            }
+        }
+        Err(err)
+    }
+}
+
+/// Reads the token from the given source of the upgrade request.
+pub fn extract_token(req: &HttpRequest, source: &TokenSource) -> std::option::Option<String> {
+    match source {
+        | TokenSource::Header { name } => {
+            let value = req.headers().get(name.as_str())?.to_str().ok()?.trim();
+            match value.get(..7) {
+                | Some(prefix) if prefix.eq_ignore_ascii_case("bearer ") => Some(value[7..].trim().to_owned()),
+                | _ => Some(value.to_owned()),
+            }
+        },
+        | TokenSource::Query { name } => {
+            actix_web::web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+                .ok()?
+                .into_inner()
+                .remove(name)
+        },
+        | TokenSource::Cookie { name } => req.cookie(name).map(|v| v.value().to_owned()),
+        | TokenSource::Subprotocol { prefix } => req
+            .headers()
+            .get_all(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .find_map(|v| v.trim().strip_prefix(prefix.as_str()).map(|v| v.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{
+        EncodingKey,
+        Header,
+    };
+
+    use super::*;
+
+    fn verifier(keys: Vec<JwtKey>) -> JwtVerifier {
+        JwtVerifier::new(&JwtAuthorizer {
+            sources: vec![TokenSource::Header {
+                name: "authorization".to_owned(),
+            }],
+            algorithms: vec![Algorithm::HS256],
+            jwks_file: None,
+            keys,
+            audience: Some(vec!["gateway".to_owned()]),
+            issuer: Some(vec!["issuer".to_owned()]),
+            leeway_sec: 0,
+            claims: Vec::new(),
+            principal_claim: None,
+        })
+        .unwrap()
+    }
+
+    fn secret(kid: std::option::Option<&str>, secret: &str) -> JwtKey {
+        JwtKey::Secret {
+            kid: kid.map(|v| v.to_owned()),
+            secret: secret.to_owned(),
+        }
+    }
+
+    /// Claims that pass the validation unless overridden.
+    fn claims(overrides: serde_json::Value) -> serde_json::Value {
+        let now = chrono::Utc::now().timestamp();
+        let mut claims = serde_json::json!({
+            "sub": "user",
+            "aud": "gateway",
+            "iss": "issuer",
+            "nbf": now - 60,
+            "exp": now + 60,
+        });
+        for (k, v) in overrides.as_object().unwrap() {
+            claims[k] = v.clone();
+        }
+        claims
+    }
+
+    fn token(alg: Algorithm, kid: std::option::Option<&str>, secret: &str, claims: &serde_json::Value) -> String {
+        let mut header = Header::new(alg);
+        header.kid = kid.map(|v| v.to_owned());
+        jsonwebtoken::encode(&header, claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn valid_tokens_are_accepted() {
+        let verifier = verifier(vec![secret(None, "secret")]);
+        let claims = verifier
+            .verify(&token(Algorithm::HS256, None, "secret", &claims(serde_json::json!({}))))
+            .unwrap();
+        assert_eq!(claims["sub"], "user");
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let verifier = verifier(vec![secret(None, "secret")]);
+        let exp = chrono::Utc::now().timestamp() - 60;
+        let claims = claims(serde_json::json!({ "exp": exp }));
+        assert!(verifier
+            .verify(&token(Algorithm::HS256, None, "secret", &claims))
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_not_yet_valid_are_rejected() {
+        let verifier = verifier(vec![secret(None, "secret")]);
+        let nbf = chrono::Utc::now().timestamp() + 60;
+        let claims = claims(serde_json::json!({ "nbf": nbf }));
+        assert!(verifier
+            .verify(&token(Algorithm::HS256, None, "secret", &claims))
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_for_other_audiences_are_rejected() {
+        let verifier = verifier(vec![secret(None, "secret")]);
+        let claims = claims(serde_json::json!({ "aud": "other" }));
+        assert!(verifier
+            .verify(&token(Algorithm::HS256, None, "secret", &claims))
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_of_other_issuers_are_rejected() {
+        let verifier = verifier(vec![secret(None, "secret")]);
+        let claims = claims(serde_json::json!({ "iss": "other" }));
+        assert!(verifier
+            .verify(&token(Algorithm::HS256, None, "secret", &claims))
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_with_disallowed_algorithms_are_rejected() {
+        let verifier = verifier(vec![secret(None, "secret")]);
+        let claims = claims(serde_json::json!({}));
+        let err = verifier
+            .verify(&token(Algorithm::HS384, None, "secret", &claims))
+            .unwrap_err();
+        assert!(err.to_string().contains("not allowed"));
+    }
+
+    #[test]
+    fn tokens_are_verified_with_the_key_of_their_kid() {
+        let verifier = verifier(vec![secret(Some("a"), "secret-a"), secret(Some("b"), "secret-b")]);
+        let claims = claims(serde_json::json!({}));
+        assert!(verifier
+            .verify(&token(Algorithm::HS256, Some("b"), "secret-b", &claims))
+            .is_ok());
+        // signed with the key of another kid
+        assert!(verifier
+            .verify(&token(Algorithm::HS256, Some("b"), "secret-a", &claims))
+            .is_err());
+        let err = verifier
+            .verify(&token(Algorithm::HS256, Some("c"), "secret-a", &claims))
+            .unwrap_err();
+        assert!(err.to_string().contains("no matching key"));
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::config::{
+    RateLimit,
+    RateLimitScope,
+    RateLimitViolation,
+    TokenBucket,
+};
+
+/// Token bucket that refills continuously at `rate` tokens per second up to
+/// its capacity.
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(settings: &TokenBucket) -> Self {
+        let capacity = settings.burst.unwrap_or(settings.rate_per_sec) as f64;
+        Self {
+            capacity,
+            rate: settings.rate_per_sec as f64,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn allows(&mut self, tokens: f64) -> bool {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * self.rate).min(self.capacity);
+        self.updated = now;
+        self.tokens >= tokens
+    }
+
+    fn take(&mut self, tokens: f64) {
+        self.tokens -= tokens;
+    }
+}
+
+/// The message and byte buckets of a single scope.
+#[derive(Debug)]
+struct Limiter {
+    messages: std::option::Option<Bucket>,
+    bytes: std::option::Option<Bucket>,
+}
+
+impl Limiter {
+    fn new(settings: &RateLimitScope) -> Self {
+        Self {
+            messages: settings.messages.as_ref().map(Bucket::new),
+            bytes: settings.bytes.as_ref().map(Bucket::new),
+        }
+    }
+
+    fn allows(&mut self, size: usize) -> bool {
+        self.messages.as_mut().is_none_or(|v| v.allows(1.0))
+            && self.bytes.as_mut().is_none_or(|v| v.allows(size as f64))
+    }
+
+    fn take(&mut self, size: usize) {
+        if let Some(v) = self.messages.as_mut() {
+            v.take(1.0);
+        }
+        if let Some(v) = self.bytes.as_mut() {
+            v.take(size as f64);
+        }
+    }
+}
+
+/// Limiters of all principals connected to this instance, keyed by endpoint
+/// and principal. A limiter lives as long as the principal has connections to
+/// the endpoint.
+#[derive(Debug, Default)]
+pub struct PrincipalLimiters {
+    entries: Mutex<HashMap<String, (usize, Limiter)>>,
+}
+
+/// Rate limits of a single connection.
+#[derive(Debug)]
+pub struct ConnectionLimits {
+    on_violation: RateLimitViolation,
+    connection: std::option::Option<Limiter>,
+    principal: std::option::Option<(String, Arc<PrincipalLimiters>)>,
+}
+
+impl ConnectionLimits {
+    /// Sets up the limits of a new connection and registers it with the
+    /// limiter of its principal.
+    pub fn new(
+        settings: &RateLimit,
+        endpoint: &str,
+        principal: std::option::Option<&str>,
+        principals: Arc<PrincipalLimiters>,
+    ) -> Self {
+        let principal = match (&settings.principal, principal) {
+            | (Some(scope), Some(principal)) => {
+                let key = format!("{}\n{}", endpoint, principal);
+                let mut entries = principals.entries.lock().unwrap(); // must never be poisoned
+                entries.entry(key.clone()).or_insert_with(|| (0, Limiter::new(scope))).0 += 1;
+                drop(entries);
+                Some((key, principals))
+            },
+            | _ => None,
+        };
+        Self {
+            on_violation: settings.on_violation,
+            connection: settings.connection.as_ref().map(Limiter::new),
+            principal,
+        }
+    }
+
+    pub fn on_violation(&self) -> RateLimitViolation {
+        self.on_violation
+    }
+
+    /// Accounts for a message of `size` bytes. Returns `false` without
+    /// consuming anything if the connection or its principal exceed a limit.
+    pub fn acquire(&mut self, size: usize) -> bool {
+        let mut entries = self.principal.as_ref().map(|(_, v)| v.entries.lock().unwrap());
+        let mut principal = match (&mut entries, &self.principal) {
+            | (Some(entries), Some((key, _))) => entries.get_mut(key).map(|v| &mut v.1),
+            | _ => None,
+        };
+        let allowed = self.connection.as_mut().is_none_or(|v| v.allows(size))
+            && principal.as_mut().is_none_or(|v| v.allows(size));
+        if allowed {
+            if let Some(v) = self.connection.as_mut() {
+                v.take(size);
+            }
+            if let Some(v) = principal {
+                v.take(size);
+            }
+        }
+        allowed
+    }
+}
+
+impl Drop for ConnectionLimits {
+    fn drop(&mut self) {
+        if let Some((key, principals)) = &self.principal {
+            let mut entries = principals.entries.lock().unwrap(); // must never be poisoned
+            if let Some(entry) = entries.get_mut(key) {
+                entry.0 -= 1;
+                if entry.0 == 0 {
+                    entries.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn bucket(rate_per_sec: u64, burst: std::option::Option<u64>) -> TokenBucket {
+        TokenBucket { rate_per_sec, burst }
+    }
+
+    fn messages(rate_per_sec: u64, burst: std::option::Option<u64>) -> RateLimitScope {
+        RateLimitScope {
+            messages: Some(bucket(rate_per_sec, burst)),
+            bytes: None,
+        }
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_up_to_its_capacity() {
+        let mut bucket = Bucket::new(&bucket(1, Some(3)));
+        for _ in 0..3 {
+            assert!(bucket.allows(1.0));
+            bucket.take(1.0);
+        }
+        assert!(!bucket.allows(1.0));
+    }
+
+    #[test]
+    fn bucket_capacity_defaults_to_the_rate() {
+        let bucket = Bucket::new(&bucket(5, None));
+        assert_eq!(bucket.capacity, 5.0);
+        assert_eq!(bucket.tokens, 5.0);
+    }
+
+    #[test]
+    fn bucket_refills_at_its_rate_up_to_its_capacity() {
+        let mut bucket = Bucket::new(&bucket(2, Some(4)));
+        bucket.take(4.0);
+        bucket.updated -= Duration::from_secs(1);
+        assert!(bucket.allows(2.0));
+        assert!(!bucket.allows(3.0));
+
+        bucket.updated -= Duration::from_secs(60);
+        assert!(bucket.allows(4.0));
+        assert_eq!(bucket.tokens, 4.0);
+    }
+
+    #[test]
+    fn connection_limit_is_enforced() {
+        let settings = RateLimit {
+            connection: Some(messages(1, Some(2))),
+            principal: None,
+            on_violation: RateLimitViolation::Drop,
+        };
+        let mut limits = ConnectionLimits::new(&settings, "/", None, Arc::default());
+        assert!(limits.acquire(10));
+        assert!(limits.acquire(10));
+        assert!(!limits.acquire(10));
+    }
+
+    #[test]
+    fn rejected_messages_consume_nothing() {
+        let settings = RateLimit {
+            connection: Some(RateLimitScope {
+                messages: Some(bucket(1, Some(2))),
+                bytes: Some(bucket(1, Some(100))),
+            }),
+            principal: None,
+            on_violation: RateLimitViolation::Drop,
+        };
+        let mut limits = ConnectionLimits::new(&settings, "/", None, Arc::default());
+        // exceeds the bytes, must not consume a message
+        assert!(!limits.acquire(200));
+        assert!(limits.acquire(50));
+        assert!(limits.acquire(50));
+        assert!(!limits.acquire(0));
+    }
+
+    #[test]
+    fn principal_limit_is_shared_by_its_connections() {
+        let settings = RateLimit {
+            connection: None,
+            principal: Some(messages(1, Some(2))),
+            on_violation: RateLimitViolation::Drop,
+        };
+        let principals = Arc::new(PrincipalLimiters::default());
+        let mut a = ConnectionLimits::new(&settings, "/", Some("user"), principals.clone());
+        let mut b = ConnectionLimits::new(&settings, "/", Some("user"), principals.clone());
+        let mut other = ConnectionLimits::new(&settings, "/", Some("other"), principals.clone());
+        assert!(a.acquire(1));
+        assert!(b.acquire(1));
+        assert!(!a.acquire(1));
+        assert!(!b.acquire(1));
+        assert!(other.acquire(1));
+
+        drop(a);
+        drop(b);
+        drop(other);
+        assert!(principals.entries.lock().unwrap().is_empty());
+    }
+}
@@ -1,19 +1,48 @@
+use hydrogen_logger::{
+    Format,
+    Level,
+};
+
+/// Applies the logging configuration, messages logged before use the
+/// defaults.
+pub fn init(
+    logging: std::option::Option<&crate::config::Logging>,
+) -> std::result::Result<(), hydrogen_logger::ConfigError> {
+    let logging = logging.cloned().unwrap_or_default();
+    hydrogen_logger::init(
+        "server.logging",
+        logging.filter.as_deref(),
+        logging.format,
+        logging.sampling,
+    )
+}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct LogMessage<'a> {
     time: String,
+    level: Level,
     instance: &'a str,
     data: Data<'a>,
 }
 
 impl<'a> LogMessage<'a> {
     pub fn now(instance: &'a str, data: Data<'a>) -> () {
-        Self {
+        let settings = hydrogen_logger::settings();
+        let (name, level) = data.meta();
+        if !settings.enabled(name, level) {
+            return;
+        }
+        let msg = Self {
             time: chrono::Utc::now().to_rfc3339(),
+            level,
             instance,
             data,
+        };
+        match settings.format() {
+            | Format::Json => msg.log(),
+            | Format::Text => println!("{}", msg.text(name)),
         }
-        .log()
     }
 
     pub fn log(&self) -> () {
@@ -22,6 +51,14 @@ impl<'a> LogMessage<'a> {
             | Err(e) => println!("{}", e),
         }
     }
+
+    /// Renders the message as `time LEVEL instance event key=value...`.
+    fn text(&self, name: &str) -> String {
+        match &self.data {
+            | Data::Event { data } => hydrogen_logger::text(&self.time, self.level, self.instance, name, data),
+            | Data::Interval { stats } => hydrogen_logger::text(&self.time, self.level, self.instance, name, stats),
+        }
+    }
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -31,14 +68,30 @@ pub enum Data<'a> {
     Interval { stats: Stats<'a> },
 }
 
+impl Data<'_> {
+    /// The name filters and sampling refer to and the level of the message.
+    fn meta(&self) -> (&'static str, Level) {
+        match self {
+            | Data::Event { data } => data.meta(),
+            | Data::Interval { stats } => stats.meta(),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Event<'a> {
     Error { err: &'a str },
     Startup { message: &'a str },
+    Shutdown { message: &'a str },
+    RedisResubscribed { connections: usize },
 
     Connect { connection: &'a str },
     Disconnect { connection: &'a str },
+    ProtocolError { connection: &'a str, err: &'a str },
+    OutMessageTooLarge { connection: &'a str, size: usize },
+    RateLimitExceeded { connection: &'a str },
+    AdmissionRejected { connection: &'a str, reason: &'a str },
     ServerDisconnect { connection: &'a str, reason: &'a str },
 
     ClientMessage { connection: &'a str },
@@ -46,16 +99,69 @@ pub enum Event<'a> {
     ServerEndpointBroadcastMessagePost { endpoint: &'a str },
     ServerBroadcastMessageEnqueue {},
     ServerBroadcastMessagePost {},
+    ServerRoomMessageEnqueue { room: &'a str },
+    ServerRoomMessagePost { room: &'a str },
     ServerMessageEnqueue { connection: &'a str },
     ServerMessagePost { connection: &'a str },
+    ServerPrincipalMessageEnqueue { principal: &'a str },
+    ServerPrincipalMessagePost { principal: &'a str },
+    ServerPrincipalDisconnect { principal: &'a str, reason: &'a str },
 
     AuthRouteResponse { connection: &'a str, response: u16 },
     ConnectRouteResponse { connection: &'a str, response: u16 },
     DisconnectRouteResponse { connection: &'a str, response: u16 },
 }
 
+impl Event<'_> {
+    fn meta(&self) -> (&'static str, Level) {
+        match self {
+            | Event::Error { .. } => ("error", Level::Error),
+            | Event::Startup { .. } => ("startup", Level::Info),
+            | Event::Shutdown { .. } => ("shutdown", Level::Info),
+            | Event::RedisResubscribed { .. } => ("redis_resubscribed", Level::Warn),
+
+            | Event::Connect { .. } => ("connect", Level::Info),
+            | Event::Disconnect { .. } => ("disconnect", Level::Info),
+            | Event::ProtocolError { .. } => ("protocol_error", Level::Warn),
+            | Event::OutMessageTooLarge { .. } => ("out_message_too_large", Level::Warn),
+            | Event::RateLimitExceeded { .. } => ("rate_limit_exceeded", Level::Warn),
+            | Event::AdmissionRejected { .. } => ("admission_rejected", Level::Warn),
+            | Event::ServerDisconnect { .. } => ("server_disconnect", Level::Info),
+
+            | Event::ClientMessage { .. } => ("client_message", Level::Debug),
+            | Event::ServerEndpointBroadcastMessageEnqueue { .. } => {
+                ("server_endpoint_broadcast_message_enqueue", Level::Debug)
+            },
+            | Event::ServerEndpointBroadcastMessagePost { .. } => {
+                ("server_endpoint_broadcast_message_post", Level::Debug)
+            },
+            | Event::ServerBroadcastMessageEnqueue {} => ("server_broadcast_message_enqueue", Level::Debug),
+            | Event::ServerBroadcastMessagePost {} => ("server_broadcast_message_post", Level::Debug),
+            | Event::ServerRoomMessageEnqueue { .. } => ("server_room_message_enqueue", Level::Debug),
+            | Event::ServerRoomMessagePost { .. } => ("server_room_message_post", Level::Debug),
+            | Event::ServerMessageEnqueue { .. } => ("server_message_enqueue", Level::Debug),
+            | Event::ServerMessagePost { .. } => ("server_message_post", Level::Debug),
+            | Event::ServerPrincipalMessageEnqueue { .. } => ("server_principal_message_enqueue", Level::Debug),
+            | Event::ServerPrincipalMessagePost { .. } => ("server_principal_message_post", Level::Debug),
+            | Event::ServerPrincipalDisconnect { .. } => ("server_principal_disconnect", Level::Info),
+
+            | Event::AuthRouteResponse { .. } => ("auth_route_response", Level::Debug),
+            | Event::ConnectRouteResponse { .. } => ("connect_route_response", Level::Debug),
+            | Event::DisconnectRouteResponse { .. } => ("disconnect_route_response", Level::Debug),
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Stats<'a> {
     Connections { count: usize, connections: Vec<&'a String> },
 }
+
+impl Stats<'_> {
+    fn meta(&self) -> (&'static str, Level) {
+        match self {
+            | Stats::Connections { .. } => ("connections", Level::Info),
+        }
+    }
+}
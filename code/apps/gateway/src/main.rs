@@ -1,21 +1,35 @@
+mod admin;
+mod admission;
 mod args;
+mod cache;
 mod config;
 mod error;
+mod jwt;
+mod limits;
 mod logger;
 mod messages;
+mod metrics;
+mod publisher;
 mod routes;
 mod server;
+mod shutdown;
 mod types;
 mod ws;
 mod handlers {
     pub mod connection;
     pub mod health;
+    pub mod metrics;
+    pub mod principal;
+    pub mod room;
     pub mod websocket;
 }
 
 use std::error::Error;
 
-use actix::Actor;
+use actix::{
+    Actor,
+    Addr,
+};
 use actix_web::{
     http,
     web::{
@@ -29,7 +43,6 @@ use nats::jetstream::JetStream;
 use server::Server;
 
 use crate::types::{
-    Endpoint,
     GroupID,
     InstanceID,
 };
@@ -51,6 +64,7 @@ async fn main() -> std::result::Result<(), Box<dyn Error>> {
 /// Main server function, starting an actix HTTP server with the various
 /// endpoints.
 async fn serve(config: crate::config::Config) -> std::result::Result<(), Box<dyn Error>> {
+    logger::init(config.server.logging.as_ref())?;
     let instance = uuid::Uuid::new_v4().to_string();
     logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
         data: logger::Event::Startup {
@@ -58,6 +72,13 @@ async fn serve(config: crate::config::Config) -> std::result::Result<(), Box<dyn
         },
     });
 
+    let tracer_provider = hydrogen_telemetry::init(
+        "hydrogen-gateway",
+        &instance,
+        &config.group_id,
+        config.server.tracing.as_ref(),
+    )?;
+
     let redis = redis::Client::open(config.redis.endpoint.clone())?;
     logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
         data: logger::Event::Startup {
@@ -72,25 +93,38 @@ async fn serve(config: crate::config::Config) -> std::result::Result<(), Box<dyn
         return Err(Box::new(crate::error::StartupError::new(e)));
     }
 
-    let js = match config.server.comms {
-        | crate::config::CommsMode::UniServerToClient => Result::<Option<JetStream>, Box<dyn Error>>::Ok(None),
-        | crate::config::CommsMode::Bidi { ref stream } => {
-            logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
-                data: logger::Event::Startup {
-                    message: &format!("nats client opening @ {}", stream.endpoint),
-                },
-            });
-            match nats::connect(&stream.endpoint) {
-                | Ok(v) => Ok(Some(nats::jetstream::new(v))),
-                | Err(e) => {
-                    logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
-                        data: logger::Event::Error { err: &e.to_string() },
-                    });
-                    return Err(Box::new(crate::error::StartupError::new(&e.to_string())));
-                },
-            }
-        },
-    }?;
+    let redis_manager = redis::aio::ConnectionManager::new(redis.clone()).await?;
+
+    // one jetstream context per nats endpoint used by the server or any of the
+    // endpoints
+    let mut js = std::collections::HashMap::<String, JetStream>::new();
+    for comms in
+        std::iter::once(&config.server.comms).chain(config.routes.endpoints.iter().filter_map(|v| v.comms.as_ref()))
+    {
+        let stream = match comms {
+            | crate::config::CommsMode::UniServerToClient => continue,
+            | crate::config::CommsMode::Bidi { stream } => stream,
+        };
+        if js.contains_key(&stream.endpoint) {
+            continue;
+        }
+        logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
+            data: logger::Event::Startup {
+                message: &format!("nats client opening @ {}", stream.endpoint),
+            },
+        });
+        match nats::connect(&stream.endpoint) {
+            | Ok(v) => {
+                js.insert(stream.endpoint.clone(), nats::jetstream::new(v));
+            },
+            | Err(e) => {
+                logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
+                    data: logger::Event::Error { err: &e.to_string() },
+                });
+                return Err(Box::new(crate::error::StartupError::new(&e.to_string())));
+            },
+        }
+    }
 
     let bind = config.server.address.clone();
     logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
@@ -99,35 +133,180 @@ async fn serve(config: crate::config::Config) -> std::result::Result<(), Box<dyn
         },
     });
 
-    let server = Server::new(config.clone(), instance.clone(), redis.clone(), js.clone()).start();
-    HttpServer::new(move || {
-        let mut app = App::new()
-            .app_data(Data::new(server.clone()))
-            .app_data(Data::new(config.clone()))
-            .app_data(Data::new(InstanceID::from(instance.clone())))
-            .app_data(Data::new(GroupID::from(config.group_id.clone())))
-            .service(crate::handlers::connection::handle_broadcast_message)
-            .service(crate::handlers::connection::handle_server_message)
-            .service(crate::handlers::connection::handle_disconnect)
-            .service(crate::handlers::health::handler);
+    let server = Server::new(config.clone(), instance.clone(), redis.clone(), redis_manager, js).start();
+    crate::metrics::init();
+
+    // endpoints without own authorizers share the verifier and cache
+    let shared_auth = make_authorizers(config.routes.authorizer.as_ref(), config.routes.jwt_authorizer.as_ref())?;
+    let mut endpoints = Vec::new();
+    for ep in config.routes.endpoints.iter() {
+        let auth = if ep.overrides_authorizer() {
+            make_authorizers(ep.authorizer.as_ref(), ep.jwt_authorizer.as_ref())?
+        } else {
+            shared_auth.clone()
+        };
+        endpoints.push((ep.clone(), auth));
+    }
 
-        for ep in config.clone().routes.endpoints {
-            assert!(ep.starts_with("/"), "routes need to start with a forward slash");
+    let principal_limiters = Data::new(crate::limits::PrincipalLimiters::default());
+    let instance_connections = Data::new(crate::admission::InstanceConnections::default());
+    let draining = Data::new(crate::shutdown::Draining::default());
 
-            app = app.service(
-                web::resource(format!("/ws{}", ep))
+    // the management endpoints and the metrics are only served on the public
+    // bind if there is no dedicated admin bind
+    let admin = config.server.admin.clone();
+    let admin_bind = admin.as_ref().and_then(|v| v.address.clone());
+    let public = {
+        let (server, config, instance) = (server.clone(), config.clone(), instance.clone());
+        let draining = draining.clone();
+        let management = admin_bind.is_none();
+        HttpServer::new(move || {
+            let mut app = App::new()
+                .configure(|cfg| configure_data(cfg, &server, &config, &instance))
+                .app_data(principal_limiters.clone())
+                .app_data(instance_connections.clone())
+                .app_data(draining.clone())
+                .service(crate::handlers::health::handler)
+                .service(crate::handlers::health::liveness)
+                .service(crate::handlers::health::readiness);
+            if management {
+                app = app
+                    .service(crate::handlers::metrics::handler)
+                    .configure(configure_management);
+            }
+
+            for (ep, (jwt, cache)) in endpoints.clone() {
+                assert!(ep.path.starts_with("/"), "routes need to start with a forward slash");
+
+                let mut resource = web::resource(format!("/ws{}", ep.path))
                     .route(
                         web::get()
                             .method(http::Method::GET)
                             .to(crate::handlers::websocket::handler),
                     )
-                    .app_data(Data::new(Endpoint::from(ep.clone()))),
-            )
-        }
-        app
-    })
-    .bind(&bind)?
-    .run()
-    .await?;
+                    .app_data(Data::new(ep));
+                if let Some(jwt) = jwt {
+                    resource = resource.app_data(jwt);
+                }
+                if let Some(cache) = cache {
+                    resource = resource.app_data(cache);
+                }
+                app = app.service(resource)
+            }
+            app
+        })
+        .disable_signals()
+        .bind(&bind)?
+        .run()
+    };
+    let mut servers = vec![public];
+
+    if let (Some(admin_bind), Some(admin)) = (admin_bind, admin) {
+        logger::LogMessage::now(&instance.to_string(), logger::Data::Event {
+            data: logger::Event::Startup {
+                message: &format!("instance will bind admin @ {}", &admin_bind),
+            },
+        });
+        let (server, config, instance) = (server.clone(), config.clone(), instance.clone());
+        let draining = draining.clone();
+        let management = HttpServer::new(move || {
+            App::new()
+                .configure(|cfg| configure_data(cfg, &server, &config, &instance))
+                .app_data(draining.clone())
+                .service(crate::handlers::health::handler)
+                .service(crate::handlers::health::liveness)
+                .service(crate::handlers::health::readiness)
+                .service(crate::handlers::metrics::handler)
+                .configure(configure_management)
+        })
+        .disable_signals()
+        .on_connect(crate::admin::on_connect);
+        let management = match admin.auth {
+            | crate::config::AdminAuth::Mtls {
+                ref ca_cert,
+                ref cert,
+                ref key,
+                ..
+            } => management.bind_openssl(&admin_bind, crate::admin::make_acceptor(ca_cert, cert, key)?)?,
+            | _ => management.bind(&admin_bind)?,
+        };
+        servers.push(management.run());
+    }
+
+    // connections are drained on SIGTERM instead of being dropped by the
+    // servers
+    let handles = servers.iter().map(|v| v.handle()).collect();
+    let shutdown = crate::shutdown::run(
+        instance.clone(),
+        config.server.shutdown.clone().unwrap_or_default(),
+        server,
+        draining,
+        handles,
+    );
+    match futures::future::select(futures::future::try_join_all(servers), Box::pin(shutdown)).await {
+        | futures::future::Either::Left((res, _)) => {
+            res?;
+        },
+        | futures::future::Either::Right((res, servers)) => {
+            res?;
+            servers.await?;
+        },
+    }
+    // flushes the pending spans
+    if let Some(provider) = tracer_provider {
+        provider.shutdown()?;
+    }
     Ok(())
 }
+
+type Authorizers = (
+    std::option::Option<Data<crate::jwt::JwtVerifier>>,
+    std::option::Option<Data<crate::cache::AuthorizerCache>>,
+);
+
+/// Builds the JWT verifier and the authorizer cache for the given
+/// authorizers, both are shared by all workers.
+fn make_authorizers(
+    authorizer: std::option::Option<&crate::config::Authorizer>,
+    jwt_authorizer: std::option::Option<&crate::config::JwtAuthorizer>,
+) -> std::result::Result<Authorizers, Box<dyn Error>> {
+    let jwt = match jwt_authorizer {
+        | Some(v) => Some(Data::new(crate::jwt::JwtVerifier::new(v)?)),
+        | None => None,
+    };
+    let cache = authorizer
+        .and_then(|v| v.cache.clone())
+        .map(|v| Data::new(crate::cache::AuthorizerCache::new(v)));
+    Ok((jwt, cache))
+}
+
+/// Registers the application data shared by all handlers.
+fn configure_data(cfg: &mut web::ServiceConfig, server: &Addr<Server>, config: &crate::config::Config, instance: &str) {
+    cfg.app_data(Data::new(server.clone()))
+        .app_data(Data::new(config.clone()))
+        .app_data(Data::new(InstanceID::from(instance)))
+        .app_data(Data::new(GroupID::from(config.group_id.clone())));
+}
+
+/// Registers the management endpoints behind the admin auth.
+fn configure_management(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("")
+            // leaves every other path to the services registered next to it
+            .guard(actix_web::guard::fn_guard(|ctx| {
+                crate::admin::is_management(ctx.head().uri.path())
+            }))
+            .wrap(crate::admin::Authorize)
+            .service(crate::handlers::connection::handle_broadcast_message)
+            .service(crate::handlers::connection::handle_server_message)
+            .service(crate::handlers::connection::handle_disconnect)
+            .service(crate::handlers::connection::handle_get_connection)
+            .service(crate::handlers::connection::handle_list_connections)
+            .service(crate::handlers::room::handle_room_message)
+            .service(crate::handlers::room::handle_list_members)
+            .service(crate::handlers::room::handle_join)
+            .service(crate::handlers::room::handle_leave)
+            .service(crate::handlers::principal::handle_principal_message)
+            .service(crate::handlers::principal::handle_principal_disconnect),
+    );
+}
@@ -2,12 +2,58 @@ use actix::prelude::{
     Message,
     Recipient,
 };
+use hydrogen_bus::content::ContentType;
+
+/// A message payload as it is transported over a websocket connection.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Payload {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Payload {
+    /// Decodes a payload from its bus representation where binary data is
+    /// base64 encoded.
+    pub fn from_bus(content_type: ContentType, message: String) -> std::result::Result<Self, base64::DecodeError> {
+        match content_type {
+            | ContentType::Text => Ok(Self::Text(message)),
+            | ContentType::Binary => Ok(Self::Binary(base64::decode(message)?)),
+        }
+    }
+
+    /// Returns the size of the payload in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            | Self::Text(v) => v.len(),
+            | Self::Binary(v) => v.len(),
+        }
+    }
+
+    /// Encodes the payload into its bus representation where binary data is
+    /// base64 encoded.
+    pub fn into_bus(self) -> (ContentType, String) {
+        match self {
+            | Self::Text(v) => (ContentType::Text, v),
+            | Self::Binary(v) => (ContentType::Binary, base64::encode(v)),
+        }
+    }
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub enum WsMessage {
-    Message { endpoint: String, message: String },
+    Message {
+        endpoint: String,
+        message: Payload,
+        reply_to: std::option::Option<String>,
+    },
     Disconnect(String),
+    /// Closes the connection with the given close code, e.g. when the
+    /// instance shuts down.
+    Close {
+        code: u16,
+        reason: String,
+    },
 }
 
 #[derive(Message)]
@@ -17,7 +63,10 @@ pub struct Connect {
     pub endpoint: String,
     pub group_id: String,
     pub time: String,
+    pub context: ConnectionContext,
+    pub rooms: Vec<String>,
     pub addr: Recipient<WsMessage>,
+    pub trace: opentelemetry::Context,
 }
 
 #[derive(Message)]
@@ -27,20 +76,116 @@ pub struct Disconnect {
     pub endpoint: String,
     pub group_id: String,
     pub time: String,
+    pub principal: std::option::Option<String>,
+    pub trace: opentelemetry::Context,
 }
 
 #[derive(Message)]
 #[rtype(result = "std::result::Result<(), u16>")]
 pub struct Heartbeat {
     pub connection: String,
+    pub endpoint: String,
+    pub principal: std::option::Option<String>,
     pub time: String,
 }
 
+/// Closes all connections of this instance at random points within `window`.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Drain {
+    pub window: std::time::Duration,
+    pub code: u16,
+    pub reason: String,
+}
+
+/// Whether the redis subscription of this instance is established.
+#[derive(Debug, Message)]
+#[rtype(result = "bool")]
+pub struct IsSubscribed;
+
+/// Whether the redis subscriber thread of this instance is still running,
+/// answered by the server for the liveness probe.
+#[derive(Debug, Message)]
+#[rtype(result = "bool")]
+pub struct IsAlive;
+
+/// Checks the dependencies this instance needs to serve connections for the
+/// readiness probe.
+#[derive(Debug, Message)]
+#[rtype(result = "std::collections::BTreeMap<String, Check>")]
+pub struct CheckDependencies;
+
+/// The result of a single dependency check.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Check {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub err: std::option::Option<String>,
+}
+
+impl Check {
+    pub fn up() -> Self {
+        Self {
+            status: CheckStatus::Up,
+            err: None,
+        }
+    }
+
+    pub fn down(err: &str) -> Self {
+        Self {
+            status: CheckStatus::Down,
+            err: Some(err.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+/// Number of connections of this instance that are still open or whose
+/// disconnect has not been processed yet.
+#[derive(Debug, Message)]
+#[rtype(result = "usize")]
+pub struct PendingSessions;
+
+/// Admission of a new connection against the limits that are shared by all
+/// instances of the group. Resolves to `false` if a limit is reached,
+/// otherwise the connection is counted until it disconnects or misses its
+/// heartbeats.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<bool, u16>")]
+pub struct Admit {
+    pub connection: String,
+    pub endpoint: String,
+    pub principal: std::option::Option<String>,
+    pub max_endpoint_connections: std::option::Option<usize>,
+    pub max_principal_connections: std::option::Option<usize>,
+}
+
+/// Removes a connection that never started from the admission counts of the
+/// group.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<(), u16>")]
+pub struct Release {
+    pub connection: String,
+    pub endpoint: String,
+    pub principal: std::option::Option<String>,
+}
+
 pub type ConnectionContextMap = std::collections::HashMap<String, serde_json::Value>;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ConnectionContext {
     pub authorizer: std::option::Option<ConnectionContextMap>,
+    #[serde(default)]
+    pub principal: std::option::Option<String>,
+    #[serde(default)]
+    pub subprotocol: std::option::Option<String>,
 }
 
 #[derive(Debug, Message, serde::Serialize, serde::Deserialize)]
@@ -51,15 +196,184 @@ pub struct ClientMessage {
     pub endpoint: String,
     pub time: String,
     pub context: ConnectionContext,
-    pub message: String,
+    pub message: Payload,
+    #[serde(skip)]
+    pub trace: opentelemetry::Context,
+}
+
+/// Lookup of a single connection anywhere in the group.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<ConnectionInfo, u16>")]
+pub struct GetConnection {
+    pub connection: String,
+}
+
+/// Lookup of a page of connections in the group. A page is the result of a
+/// single redis `SCAN` iteration starting at `cursor`.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<ConnectionPage, u16>")]
+pub struct ListConnections {
+    pub endpoint: std::option::Option<String>,
+    pub cursor: u64,
+    pub count: usize,
+}
+
+/// Details about a connection as registered in redis by its owning instance.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ConnectionInfo {
+    pub connection_id: String,
+    pub instance_id: String,
+    pub endpoint: String,
+    pub connected_at: String,
+    pub last_heartbeat: String,
+    pub context: ConnectionContext,
+}
+
+impl ConnectionInfo {
+    /// Reads the connection details from the fields of its redis hash.
+    pub fn from_hash(
+        connection: &str,
+        mut hash: std::collections::HashMap<String, String>,
+    ) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        let mut field = |name: &str| {
+            hash.remove(name).ok_or_else(|| {
+                crate::error::ConnectionNotFoundError::new(&format!("connection {} is missing {}", connection, name))
+            })
+        };
+        Ok(Self {
+            connection_id: connection.to_owned(),
+            instance_id: field("instance_id")?,
+            endpoint: field("endpoint")?,
+            connected_at: field("connected_at")?,
+            last_heartbeat: field("last_heartbeat")?,
+            context: serde_json::from_str(&field("context")?)?,
+        })
+    }
+}
+
+/// A page of connections, `cursor` is used to request the next page and is
+/// absent on the last one.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ConnectionPage {
+    pub cursor: std::option::Option<u64>,
+    pub connections: Vec<ConnectionInfo>,
+}
+
+/// Adds a connection to a room.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<(), u16>")]
+pub struct JoinRoom {
+    pub room: String,
+    pub connection: String,
+}
+
+/// Removes a connection from a room.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<(), u16>")]
+pub struct LeaveRoom {
+    pub room: String,
+    pub connection: String,
+}
+
+/// Lookup of the connections that are members of a room.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<Vec<String>, u16>")]
+pub struct RoomMembers {
+    pub room: String,
+}
+
+/// Message from the backend towards all members of a room.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<(), u16>")]
+pub struct RoomServerMessage {
+    pub room: String,
+    pub time: String,
+    pub message: Payload,
 }
 
+impl RoomServerMessage {
+    /// Converts the message into the bus message for the given connections
+    /// that are owned by the same instance.
+    pub fn to_bus(&self, connections: Vec<String>) -> hydrogen_bus::redis::Message {
+        let (content_type, message) = self.message.clone().into_bus();
+        hydrogen_bus::redis::Message::SRBroadcast {
+            room: self.room.clone(),
+            connections,
+            time: self.time.clone(),
+            content_type,
+            message,
+        }
+    }
+}
+
+/// Message from the backend towards all connections of a principal.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<(), u16>")]
+pub struct PrincipalServerMessage {
+    pub principal: String,
+    pub time: String,
+    pub message: Payload,
+}
+
+impl PrincipalServerMessage {
+    /// Converts the message into the bus message for the given connections
+    /// that are owned by the same instance.
+    pub fn to_bus(&self, connections: Vec<String>) -> hydrogen_bus::redis::Message {
+        let (content_type, message) = self.message.clone().into_bus();
+        hydrogen_bus::redis::Message::SPMessage {
+            principal: self.principal.clone(),
+            connections,
+            time: self.time.clone(),
+            content_type,
+            message,
+        }
+    }
+}
+
+/// Disconnect of all connections of a principal, initiated by the backend.
+#[derive(Debug, Message)]
+#[rtype(result = "std::result::Result<(), u16>")]
+pub struct PrincipalDisconnect {
+    pub principal: String,
+    pub time: String,
+    pub reason: String,
+}
+
+impl PrincipalDisconnect {
+    /// Converts the disconnect into the bus message for the given connections
+    /// that are owned by the same instance.
+    pub fn to_bus(&self, connections: Vec<String>) -> hydrogen_bus::redis::Message {
+        hydrogen_bus::redis::Message::SPDisconnect {
+            principal: self.principal.clone(),
+            connections,
+            time: self.time.clone(),
+            reason: self.reason.clone(),
+        }
+    }
+}
+
+/// Message from the backend towards a client. The result is an error status
+/// code if the connection is unknown or, in case an acknowledgement has been
+/// requested, the owning instance did not acknowledge the delivery in time.
 #[derive(Debug, Message, serde::Serialize, serde::Deserialize)]
-#[rtype(result = "()")]
+#[rtype(result = "std::result::Result<(), u16>")]
 pub struct ServerMessage {
     pub connection: String,
     pub time: String,
-    pub message: String,
+    pub message: Payload,
+    /// Duration to wait for the owning instance to acknowledge the delivery.
+    pub ack: std::option::Option<std::time::Duration>,
+}
+
+/// Acknowledges the delivery of a message by publishing the reply onto the
+/// given reply channel.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Reply {
+    pub reply_to: String,
+    pub reply: hydrogen_bus::redis::Reply,
 }
 
 #[derive(Debug, Message, serde::Serialize, serde::Deserialize)]
@@ -67,17 +381,19 @@ pub struct ServerMessage {
 pub enum BroadcastServerMessage {
     All {
         time: String,
-        message: String,
+        message: Payload,
     },
     Endpoint {
         endpoint: String,
         time: String,
-        message: String,
+        message: Payload,
     },
 }
 
+/// Disconnect request from the backend for a client. The result is an error
+/// status code if the connection is unknown.
 #[derive(Debug, Message, serde::Serialize, serde::Deserialize)]
-#[rtype(result = "()")]
+#[rtype(result = "std::result::Result<(), u16>")]
 pub struct ServerDisconnect {
     pub connection: String,
     pub time: String,
@@ -88,27 +404,41 @@ impl Into<hydrogen_bus::redis::Message> for BroadcastServerMessage {
     fn into(self) -> hydrogen_bus::redis::Message {
         match self {
             | BroadcastServerMessage::All { time, message } => {
-                hydrogen_bus::redis::Message::SBroadcast { time, message }
+                let (content_type, message) = message.into_bus();
+                hydrogen_bus::redis::Message::SBroadcast {
+                    time,
+                    content_type,
+                    message,
+                }
             },
             | BroadcastServerMessage::Endpoint {
                 endpoint,
                 time,
                 message,
-            } => hydrogen_bus::redis::Message::SEBroadcast {
-                endpoint,
-                time,
-                message,
+            } => {
+                let (content_type, message) = message.into_bus();
+                hydrogen_bus::redis::Message::SEBroadcast {
+                    endpoint,
+                    time,
+                    content_type,
+                    message,
+                }
             },
         }
     }
 }
 
-impl Into<hydrogen_bus::redis::Message> for ServerMessage {
-    fn into(self) -> hydrogen_bus::redis::Message {
+impl ServerMessage {
+    /// Converts the message into its bus representation, asking the owning
+    /// instance to reply on the given channel.
+    pub fn into_bus(self, reply_to: std::option::Option<String>) -> hydrogen_bus::redis::Message {
+        let (content_type, message) = self.message.into_bus();
         hydrogen_bus::redis::Message::S2CMessage {
-            connection: self.connection.to_string(),
+            connection: self.connection,
             time: self.time,
-            message: self.message,
+            content_type,
+            message,
+            reply_to,
         }
     }
 }
@@ -127,6 +457,8 @@ impl Into<hydrogen_bus::nats::ConnectionContext> for ConnectionContext {
     fn into(self) -> hydrogen_bus::nats::ConnectionContext {
         hydrogen_bus::nats::ConnectionContext {
             authorizer: self.authorizer,
+            principal: self.principal,
+            subprotocol: self.subprotocol,
         }
     }
 }
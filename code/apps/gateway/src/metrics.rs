@@ -0,0 +1,111 @@
+use prometheus::{
+    register_histogram,
+    register_histogram_vec,
+    register_int_counter,
+    register_int_counter_vec,
+    register_int_gauge_vec,
+    Encoder,
+    Histogram,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGaugeVec,
+};
+
+// the metrics are registered with the default registry on first use and
+// exposed through `/metrics`
+lazy_static::lazy_static! {
+    /// Open connections of this instance per endpoint.
+    pub static ref CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "hydrogen_gateway_connections",
+        "Open connections of this instance per endpoint.",
+        &["endpoint"]
+    )
+    .unwrap();
+    /// Upgrade requests per endpoint and result (`accepted`, `unauthorized`,
+    /// `admission` or `draining`).
+    pub static ref UPGRADES: IntCounterVec = register_int_counter_vec!(
+        "hydrogen_gateway_upgrades_total",
+        "Upgrade requests per endpoint and result.",
+        &["endpoint", "result"]
+    )
+    .unwrap();
+    /// Latency of the authorizer, connect and disconnect routes.
+    pub static ref ROUTE_DURATION: HistogramVec = register_histogram_vec!(
+        "hydrogen_gateway_route_duration_seconds",
+        "Latency of the authorizer, connect and disconnect routes.",
+        &["route"]
+    )
+    .unwrap();
+    /// Responses of the authorizer, connect and disconnect routes per status
+    /// code, `error` if the route could not be reached.
+    pub static ref ROUTE_RESPONSES: IntCounterVec = register_int_counter_vec!(
+        "hydrogen_gateway_route_responses_total",
+        "Responses of the authorizer, connect and disconnect routes per status code.",
+        &["route", "status"]
+    )
+    .unwrap();
+    /// Messages per endpoint and direction (`in` or `out`).
+    pub static ref MESSAGES: IntCounterVec = register_int_counter_vec!(
+        "hydrogen_gateway_messages_total",
+        "Messages per endpoint and direction.",
+        &["endpoint", "direction"]
+    )
+    .unwrap();
+    /// Message payload bytes per endpoint and direction (`in` or `out`).
+    pub static ref MESSAGE_BYTES: IntCounterVec = register_int_counter_vec!(
+        "hydrogen_gateway_message_bytes_total",
+        "Message payload bytes per endpoint and direction.",
+        &["endpoint", "direction"]
+    )
+    .unwrap();
+    /// Messages that could not be published into redis.
+    pub static ref REDIS_PUBLISH_FAILURES: IntCounter = register_int_counter!(
+        "hydrogen_gateway_redis_publish_failures_total",
+        "Messages that could not be published into redis."
+    )
+    .unwrap();
+    /// Latency of publishing client messages into JetStream.
+    pub static ref JETSTREAM_PUBLISH_DURATION: Histogram = register_histogram!(
+        "hydrogen_gateway_jetstream_publish_duration_seconds",
+        "Latency of publishing client messages into JetStream."
+    )
+    .unwrap();
+}
+
+/// Registers all metrics so that they are exposed before their first use.
+pub fn init() {
+    lazy_static::initialize(&CONNECTIONS);
+    lazy_static::initialize(&UPGRADES);
+    lazy_static::initialize(&ROUTE_DURATION);
+    lazy_static::initialize(&ROUTE_RESPONSES);
+    lazy_static::initialize(&MESSAGES);
+    lazy_static::initialize(&MESSAGE_BYTES);
+    lazy_static::initialize(&REDIS_PUBLISH_FAILURES);
+    lazy_static::initialize(&JETSTREAM_PUBLISH_DURATION);
+}
+
+/// Records a call of the given route that started at `started`. `status` is
+/// `None` if the route could not be reached.
+pub fn observe_route(route: &str, started: std::time::Instant, status: std::option::Option<u16>) {
+    ROUTE_DURATION
+        .with_label_values(&[route])
+        .observe(started.elapsed().as_secs_f64());
+    let status = status.map(|v| v.to_string()).unwrap_or_else(|| "error".to_owned());
+    ROUTE_RESPONSES.with_label_values(&[route, &status]).inc();
+}
+
+/// Records a message of `size` bytes on the given endpoint.
+pub fn observe_message(endpoint: &str, direction: &str, size: usize) {
+    MESSAGES.with_label_values(&[endpoint, direction]).inc();
+    MESSAGE_BYTES
+        .with_label_values(&[endpoint, direction])
+        .inc_by(size as u64);
+}
+
+/// Encodes all metrics in the prometheus text format.
+pub fn encode() -> std::result::Result<String, prometheus::Error> {
+    let mut buffer = Vec::new();
+    prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
@@ -0,0 +1,62 @@
+use std::sync::{
+    mpsc,
+    Arc,
+};
+
+/// A client message on its way into a JetStream stream.
+struct Publish {
+    subject: String,
+    stream: String,
+    payload: String,
+    trace: opentelemetry::Context,
+}
+
+/// Publishes the client messages for one JetStream endpoint from a thread of
+/// its own. Messages are published one after the other in the order they were
+/// enqueued, which keeps the messages of every connection in order.
+pub struct Publisher {
+    tx: mpsc::Sender<Publish>,
+}
+
+impl Publisher {
+    pub fn start(instance: String, js: Arc<nats::jetstream::JetStream>) -> Self {
+        let (tx, rx) = mpsc::channel::<Publish>();
+        std::thread::spawn(move || {
+            // ends once the server and with it the sender is dropped
+            for msg in rx {
+                let timer = crate::metrics::JETSTREAM_PUBLISH_DURATION.start_timer();
+                let res = js.publish_with_options(&msg.subject, msg.payload, &nats::jetstream::PublishOptions {
+                    expected_stream: Some(msg.stream),
+                    ..Default::default()
+                });
+                timer.observe_duration();
+                if let Err(e) = res {
+                    hydrogen_telemetry::record_error(&msg.trace, &e);
+                    crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
+                        data: crate::logger::Event::Error { err: &e.to_string() },
+                    });
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    /// Enqueues the message for publishing. The span in `trace` ends once the
+    /// message is published.
+    pub fn publish(
+        &self,
+        subject: String,
+        stream: String,
+        payload: String,
+        trace: opentelemetry::Context,
+    ) -> std::result::Result<(), crate::error::PublishError> {
+        self.tx
+            .send(Publish {
+                subject,
+                stream,
+                payload,
+                trace,
+            })
+            .map_err(|_| crate::error::PublishError::new("publisher thread has stopped"))
+    }
+}
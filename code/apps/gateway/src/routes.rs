@@ -4,17 +4,102 @@ pub struct AuthorizerRequest {
     pub instance_id: String,
     pub group_id: String,
     pub endpoint: String,
+    pub path: String,
     pub connection_id: String,
     pub time: String,
     pub headers: Vec<(String, String)>,
+    pub query: String,
+    pub cookies: std::collections::HashMap<String, String>,
+    pub remote_addr: std::option::Option<String>,
+    pub subprotocols: Vec<String>,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct AuthorizerResponse {
     pub context: std::option::Option<crate::ws::WsConnContextMap>,
+    pub principal: std::option::Option<String>,
+    #[serde(default)]
+    pub rooms: Vec<String>,
 }
 
+/// Response of the authorizer when it does not permit the connection,
+/// describing the response that is returned to the client.
+#[derive(Debug, Clone)]
+pub struct AuthorizerRejection {
+    pub status: u16,
+    pub headers: std::collections::HashMap<String, String>,
+    pub body: String,
+}
+
+/// Optional body of a rejection, the fields that are left out are taken from
+/// the response of the authorizer itself.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct RejectionBody {
+    status: std::option::Option<u16>,
+    #[serde(default)]
+    headers: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    body: String,
+}
+
+/// Headers of the authorizer response that describe its own transfer and are
+/// not passed on to the client.
+const SKIPPED_REJECTION_HEADERS: [&str; 8] = [
+    "connection",
+    "content-length",
+    "content-type",
+    "date",
+    "keep-alive",
+    "server",
+    "transfer-encoding",
+    "upgrade",
+];
+
+impl AuthorizerRejection {
+    fn default_status() -> u16 {
+        401
+    }
+
+    /// Builds the rejection from the status, headers and body of the
+    /// authorizer response. Headers and status of the body take precedence.
+    pub fn from_response(status: u16, headers: Vec<(String, String)>, body: &str) -> Self {
+        let rejection = serde_json::from_str::<RejectionBody>(body).unwrap_or_default();
+        let mut merged: std::collections::HashMap<String, String> = headers
+            .into_iter()
+            .filter(|(k, _)| !SKIPPED_REJECTION_HEADERS.iter().any(|v| v.eq_ignore_ascii_case(k)))
+            .collect();
+        for (k, v) in rejection.headers {
+            merged.retain(|name, _| !name.eq_ignore_ascii_case(&k));
+            merged.insert(k, v);
+        }
+        Self {
+            status: rejection.status.unwrap_or(status),
+            headers: merged,
+            body: rejection.body,
+        }
+    }
+}
+
+impl Default for AuthorizerRejection {
+    fn default() -> Self {
+        Self {
+            status: Self::default_status(),
+            headers: std::collections::HashMap::new(),
+            body: String::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for AuthorizerRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "connection rejected with status {}", self.status)
+    }
+}
+
+impl std::error::Error for AuthorizerRejection {}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ConnectRequest {
@@ -25,6 +110,13 @@ pub struct ConnectRequest {
     pub time: String,
 }
 
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ConnectResponse {
+    #[serde(default)]
+    pub rooms: Vec<String>,
+}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct DisconnectRequest {
@@ -34,3 +126,39 @@ pub struct DisconnectRequest {
     pub connection_id: String,
     pub time: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn rejection_defaults_to_the_authorizer_response() {
+        let rejection = AuthorizerRejection::from_response(
+            429,
+            headers(&[("Retry-After", "5"), ("Content-Length", "0"), ("Date", "now")]),
+            "",
+        );
+        assert_eq!(rejection.status, 429);
+        assert_eq!(rejection.headers.len(), 1);
+        assert_eq!(rejection.headers.get("Retry-After").map(String::as_str), Some("5"));
+        assert_eq!(rejection.body, "");
+    }
+
+    #[test]
+    fn rejection_body_takes_precedence() {
+        let rejection = AuthorizerRejection::from_response(
+            403,
+            headers(&[("Retry-After", "5"), ("X-Reason", "banned")]),
+            r#"{"status": 429, "headers": {"retry-after": "10"}, "body": "slow down"}"#,
+        );
+        assert_eq!(rejection.status, 429);
+        assert_eq!(rejection.headers.len(), 2);
+        assert_eq!(rejection.headers.get("retry-after").map(String::as_str), Some("10"));
+        assert_eq!(rejection.headers.get("X-Reason").map(String::as_str), Some("banned"));
+        assert_eq!(rejection.body, "slow down");
+    }
+}
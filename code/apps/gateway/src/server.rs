@@ -1,39 +1,98 @@
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
 };
 
 use actix::prelude::{
     Actor,
+    ActorFutureExt,
+    AsyncContext,
     Context,
     Handler,
+    ResponseActFuture,
+    StreamHandler,
+    WrapFuture,
 };
+use actix_web::HttpMessage;
+use futures::StreamExt;
+use rand::Rng;
+use redis::aio::ConnectionManager;
 use uuid::Uuid;
 
 use crate::{
     config::CommsMode,
     messages::{
+        Admit,
         BroadcastServerMessage,
+        Check,
+        CheckDependencies,
         ClientMessage,
         Connect,
+        ConnectionInfo,
+        ConnectionPage,
         Disconnect,
+        Drain,
+        GetConnection,
         Heartbeat,
+        IsAlive,
+        IsSubscribed,
+        JoinRoom,
+        LeaveRoom,
+        ListConnections,
+        PendingSessions,
+        PrincipalDisconnect,
+        PrincipalServerMessage,
+        Release,
+        Reply,
+        RoomMembers,
+        RoomServerMessage,
         ServerDisconnect,
         ServerMessage,
     },
 };
 
 type Socket = actix::prelude::Recipient<crate::messages::WsMessage>;
+
+const REDIS_BACKOFF_MIN: std::time::Duration = std::time::Duration::from_millis(100);
+const REDIS_BACKOFF_MAX: std::time::Duration = std::time::Duration::from_secs(30);
+/// Time a single dependency check of the readiness probe may take.
+pub const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Signals that the redis subscription has been re-established.
+struct Resubscribed;
+
+/// What a connection of this instance registered in redis, kept to restore it
+/// once redis becomes available again.
+struct Registration {
+    info: [(&'static str, String); 5],
+    principal: std::option::Option<String>,
+}
 type SharedSessionMap = std::sync::Arc<std::sync::RwLock<HashMap<String, (String, Socket)>>>;
 
 pub struct Server {
     config: crate::config::Config,
     instance: String,
     sessions: SharedSessionMap,
-    redis: std::sync::Arc<redis::Client>,
-    nats_js: Option<std::sync::Arc<nats::jetstream::JetStream>>,
+    redis_client: std::sync::Arc<redis::Client>,
+    redis: ConnectionManager,
+    http: awc::Client,
+    nats_js: HashMap<String, std::sync::Arc<nats::jetstream::JetStream>>,
+    publishers: HashMap<String, crate::publisher::Publisher>,
+    /// Disconnects whose redis cleanup and disconnect route are in flight.
+    pending_disconnects: usize,
+    registrations: HashMap<String, Registration>,
+    subscribed: std::sync::Arc<AtomicBool>,
+    resubscribed: std::option::Option<futures::channel::mpsc::UnboundedReceiver<Resubscribed>>,
 
-    #[allow(dead_code)]
     redis_thread: std::thread::JoinHandle<()>,
     #[allow(dead_code)]
     stats_reporting_thread: std::option::Option<std::thread::JoinHandle<()>>,
@@ -44,17 +103,22 @@ impl Server {
         config: crate::config::Config,
         instance: String,
         redis: redis::Client,
-        nats_js: Option<nats::jetstream::JetStream>,
+        redis_manager: ConnectionManager,
+        nats_js: HashMap<String, nats::jetstream::JetStream>,
     ) -> Self {
         let redis_connection_arc = std::sync::Arc::new(redis);
         let session_map_arc: SharedSessionMap =
             std::sync::Arc::new(std::sync::RwLock::new(HashMap::<String, (String, Socket)>::new()));
 
+        let subscribed = std::sync::Arc::new(AtomicBool::new(false));
+        let (resubscribed_tx, resubscribed_rx) = futures::channel::mpsc::unbounded();
         let rt = Self::start_redis_thread(
             instance.clone(),
             config.group_id.clone(),
             redis_connection_arc.clone(),
             session_map_arc.clone(),
+            subscribed.clone(),
+            resubscribed_tx,
         );
         let srt = match config.server.stats_interval_sec {
             | Some(v) => Some(Self::start_stats_reporting_thread(
@@ -65,142 +129,334 @@ impl Server {
             | None => None,
         };
 
+        let nats_js = nats_js
+            .into_iter()
+            .map(|(k, v)| (k, Arc::new(v)))
+            .collect::<HashMap<_, _>>();
+        let publishers = nats_js
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.clone(),
+                    crate::publisher::Publisher::start(instance.clone(), v.clone()),
+                )
+            })
+            .collect();
+
         Server {
             config,
             instance,
             sessions: session_map_arc,
-            redis: redis_connection_arc,
-            nats_js: match nats_js {
-                | Some(v) => Some(Arc::new(v)),
-                | None => None,
-            },
+            redis_client: redis_connection_arc,
+            redis: redis_manager,
+            http: awc::Client::default(),
+            nats_js,
+            publishers,
+            pending_disconnects: 0,
+            registrations: HashMap::new(),
+            subscribed,
+            resubscribed: Some(resubscribed_rx),
             redis_thread: rt,
             stats_reporting_thread: srt,
         }
     }
 
     pub fn make_key(&self, connection: &str) -> String {
+        self.make_instance_key(&self.instance, connection)
+    }
+
+    pub fn make_instance_key(&self, instance: &str, connection: &str) -> String {
+        format!("{}{}:{}", self.make_key_prefix(), instance, connection)
+    }
+
+    /// Prefix shared by the instance-to-client keys of all instances in the
+    /// group.
+    pub fn make_key_prefix(&self) -> String {
+        format!("hydrogen:group:{}:i2c:", self.config.group_id)
+    }
+
+    pub fn make_reverse_key(&self, connection: &str) -> String {
+        format!("{}{}", self.make_reverse_key_prefix(), connection)
+    }
+
+    /// Prefix shared by the client-to-instance keys of the group.
+    pub fn make_reverse_key_prefix(&self) -> String {
+        format!("hydrogen:group:{}:c2i:", self.config.group_id)
+    }
+
+    /// Key of the set holding the connections that are members of the room.
+    pub fn make_room_key(&self, room: &str) -> String {
+        format!("{}{}", self.make_room_key_prefix(), room)
+    }
+
+    /// Prefix shared by the room keys of the group.
+    pub fn make_room_key_prefix(&self) -> String {
+        format!("hydrogen:group:{}:room:", self.config.group_id)
+    }
+
+    /// Key of the set holding the connections of the principal.
+    pub fn make_principal_key(&self, principal: &str) -> String {
+        format!("hydrogen:group:{}:principal:{}", self.config.group_id, principal)
+    }
+
+    /// Key of the sorted set counting the connections to the endpoint for
+    /// admission, scored by their expiry.
+    pub fn make_admission_endpoint_key(&self, endpoint: &str) -> String {
         format!(
-            "hydrogen:group:{}:i2c:{}:{}",
-            self.config.group_id,
-            self.instance,
-            connection.to_string()
+            "hydrogen:group:{}:admission:endpoint:{}",
+            self.config.group_id, endpoint
         )
     }
 
-    pub fn make_reverse_key(&self, connection: &str) -> String {
-        format!("hydrogen:group:{}:c2i:{}", self.config.group_id, connection)
+    /// Key of the sorted set counting the connections of the principal for
+    /// admission, scored by their expiry.
+    pub fn make_admission_principal_key(&self, principal: &str) -> String {
+        format!(
+            "hydrogen:group:{}:admission:principal:{}",
+            self.config.group_id, principal
+        )
+    }
+
+    /// Key of the set holding the rooms the connection is a member of.
+    pub fn make_rooms_key(&self, connection: &str) -> String {
+        format!("hydrogen:group:{}:c2r:{}", self.config.group_id, connection)
     }
 
     /// Function will start a new thread and return it's JoinHandle. This thread
-    /// will listen to the redis pub/sub channel that's relevant
-    /// for this instance and process it's messages.
+    /// will listen to the redis pub/sub channels that are relevant for this
+    /// instance and process their messages. The subscription is re-established
+    /// with exponential backoff whenever it fails, `subscribed` reflects its
+    /// state and every re-established subscription is reported on
+    /// `resubscribed`.
     fn start_redis_thread(
         instance_id: String,
         group_id: String,
         redis_conn: std::sync::Arc<redis::Client>,
         sessions: SharedSessionMap,
+        subscribed: std::sync::Arc<AtomicBool>,
+        resubscribed: futures::channel::mpsc::UnboundedSender<Resubscribed>,
     ) -> std::thread::JoinHandle<()> {
         std::thread::spawn(move || {
-            let mut conn = redis_conn.get_connection().unwrap();
-            let mut ps = conn.as_pubsub();
-            let thread_instance_id = instance_id.clone();
-            ps.subscribe(format!("hydrogen:group:{}:broadcast", group_id)).unwrap();
-            ps.subscribe(format!("hydrogen:instance:{}", instance_id)).unwrap();
-
+            let mut backoff = REDIS_BACKOFF_MIN;
+            let mut initial = true;
             loop {
-                let mut safecall = || -> Result<(), Box<dyn std::error::Error>> {
-                    let msg = ps.get_message()?;
-                    let pl: String = msg.get_payload()?;
-                    let payload: hydrogen_bus::redis::Message = serde_json::from_str(&pl)?;
-                    match payload {
-                        | hydrogen_bus::redis::Message::SBroadcast { message, .. } => {
-                            crate::logger::LogMessage::now(&thread_instance_id, crate::logger::Data::Event {
-                                data: crate::logger::Event::ServerBroadcastMessagePost {},
-                            });
-
-                            for s in sessions.read()?.iter() {
-                                s.1 .1.do_send(crate::messages::WsMessage::Message {
-                                    endpoint: s.1 .0.to_owned(),
-                                    message: message.clone(),
-                                });
-                            }
-                            Ok(())
-                        },
-                        | hydrogen_bus::redis::Message::SEBroadcast { endpoint, message, .. } => {
-                            crate::logger::LogMessage::now(&thread_instance_id, crate::logger::Data::Event {
-                                data: crate::logger::Event::ServerEndpointBroadcastMessagePost { endpoint: &endpoint },
-                            });
+                let mut subscribe = || -> Result<(), Box<dyn std::error::Error>> {
+                    let mut conn = redis_conn.get_connection()?;
+                    // names the subscription so it can be told apart in `CLIENT LIST`
+                    redis::cmd("CLIENT")
+                        .arg("SETNAME")
+                        .arg(format!("hydrogen:group:{}:instance:{}", group_id, instance_id))
+                        .query::<()>(&mut conn)?;
+                    let mut ps = conn.as_pubsub();
+                    ps.subscribe(hydrogen_bus::channels::broadcast(&group_id))?;
+                    ps.subscribe(hydrogen_bus::channels::instance(&instance_id))?;
+                    subscribed.store(true, Ordering::SeqCst);
+                    backoff = REDIS_BACKOFF_MIN;
+                    if !initial {
+                        // the receiver only goes away with the server
+                        let _ = resubscribed.unbounded_send(Resubscribed);
+                    }
+                    initial = false;
 
-                            for s in sessions.read()?.iter().filter(|s| s.1 .0 == endpoint) {
-                                s.1 .1.do_send(crate::messages::WsMessage::Message {
-                                    endpoint: s.1 .0.to_owned(),
-                                    message: message.clone(),
-                                });
-                            }
-                            Ok(())
-                        },
-                        // Handles messages for connections this instance owns.
-                        | hydrogen_bus::redis::Message::S2CMessage {
-                            connection,
-                            time: _,
-                            message,
-                        } => {
-                            crate::logger::LogMessage::now(&thread_instance_id, crate::logger::Data::Event {
-                                data: crate::logger::Event::ServerMessagePost {
-                                    connection: &connection,
-                                },
+                    loop {
+                        let msg = ps.get_message()?;
+                        if let Err(e) = Self::handle_redis_message(&instance_id, &redis_conn, &sessions, msg) {
+                            crate::logger::LogMessage::now(&instance_id, crate::logger::Data::Event {
+                                data: crate::logger::Event::Error { err: &e.to_string() },
                             });
-                            match sessions.read()?.get(&connection) {
-                                | Some(s) => {
-                                    s.1.do_send(crate::messages::WsMessage::Message {
-                                        endpoint: s.0.to_owned(),
-                                        message: message.clone(),
-                                    });
-                                    Ok(())
-                                },
-                                | None => Err(Box::new(crate::error::ConnectionNotFoundError::new(
-                                    &connection.to_string(),
-                                ))),
-                            }
-                        },
-                        // Handles server disconnect requests for a connection this instance owns..
-                        | hydrogen_bus::redis::Message::SDisconnect {
-                            connection,
-                            time: _,
-                            reason,
-                        } => {
-                            crate::logger::LogMessage::now(&thread_instance_id, crate::logger::Data::Event {
-                                data: crate::logger::Event::ServerDisconnect {
-                                    connection: &connection,
-                                    reason: &reason,
-                                },
-                            });
-                            match sessions.read()?.get(&connection) {
-                                | Some(s) => {
-                                    s.1.do_send(crate::messages::WsMessage::Disconnect(reason));
-                                    Ok(())
-                                },
-                                | None => Err(Box::new(crate::error::ConnectionNotFoundError::new(
-                                    &connection.to_string(),
-                                ))),
-                            }
-                        },
+                        }
                     }
                 };
-                match safecall() {
-                    | Ok(_) => {},
-                    | Err(e) => {
-                        crate::logger::LogMessage::now(&thread_instance_id, crate::logger::Data::Event {
-                            data: crate::logger::Event::Error { err: &e.to_string() },
-                        });
-                        std::thread::sleep(std::time::Duration::from_secs(5));
-                    },
+                let res = subscribe();
+                subscribed.store(false, Ordering::SeqCst);
+                if let Err(e) = res {
+                    crate::logger::LogMessage::now(&instance_id, crate::logger::Data::Event {
+                        data: crate::logger::Event::Error {
+                            err: &format!("redis subscription failed, retrying in {:?}: {}", backoff, e),
+                        },
+                    });
                 }
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(REDIS_BACKOFF_MAX);
             }
         })
     }
 
+    /// Processes a message received on one of the redis pub/sub channels of
+    /// this instance.
+    fn handle_redis_message<'a>(
+        thread_instance_id: &str,
+        redis_conn: &redis::Client,
+        sessions: &'a SharedSessionMap,
+        msg: redis::Msg,
+    ) -> Result<(), Box<dyn std::error::Error+'a>> {
+        let pl: String = msg.get_payload()?;
+        let payload: hydrogen_bus::redis::Message = serde_json::from_str(&pl)?;
+        match payload {
+            | hydrogen_bus::redis::Message::SBroadcast {
+                content_type, message, ..
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerBroadcastMessagePost {},
+                });
+                let message = crate::messages::Payload::from_bus(content_type, message)?;
+
+                for s in sessions.read()?.iter() {
+                    s.1 .1.do_send(crate::messages::WsMessage::Message {
+                        endpoint: s.1 .0.to_owned(),
+                        message: message.clone(),
+                        reply_to: None,
+                    });
+                }
+                Ok(())
+            },
+            | hydrogen_bus::redis::Message::SEBroadcast {
+                endpoint,
+                content_type,
+                message,
+                ..
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerEndpointBroadcastMessagePost { endpoint: &endpoint },
+                });
+                let message = crate::messages::Payload::from_bus(content_type, message)?;
+
+                for s in sessions.read()?.iter().filter(|s| s.1 .0 == endpoint) {
+                    s.1 .1.do_send(crate::messages::WsMessage::Message {
+                        endpoint: s.1 .0.to_owned(),
+                        message: message.clone(),
+                        reply_to: None,
+                    });
+                }
+                Ok(())
+            },
+            // Handles messages for the members of a room this instance owns.
+            | hydrogen_bus::redis::Message::SRBroadcast {
+                room,
+                connections,
+                content_type,
+                message,
+                ..
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerRoomMessagePost { room: &room },
+                });
+                let message = crate::messages::Payload::from_bus(content_type, message)?;
+
+                let sessions = sessions.read()?;
+                for s in connections.iter().filter_map(|v| sessions.get(v)) {
+                    s.1.do_send(crate::messages::WsMessage::Message {
+                        endpoint: s.0.to_owned(),
+                        message: message.clone(),
+                        reply_to: None,
+                    });
+                }
+                Ok(())
+            },
+            // Handles messages for the connections of a principal this instance owns.
+            | hydrogen_bus::redis::Message::SPMessage {
+                principal,
+                connections,
+                content_type,
+                message,
+                ..
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerPrincipalMessagePost { principal: &principal },
+                });
+                let message = crate::messages::Payload::from_bus(content_type, message)?;
+
+                let sessions = sessions.read()?;
+                for s in connections.iter().filter_map(|v| sessions.get(v)) {
+                    s.1.do_send(crate::messages::WsMessage::Message {
+                        endpoint: s.0.to_owned(),
+                        message: message.clone(),
+                        reply_to: None,
+                    });
+                }
+                Ok(())
+            },
+            // Handles disconnects of the connections of a principal this instance owns.
+            | hydrogen_bus::redis::Message::SPDisconnect {
+                principal,
+                connections,
+                reason,
+                ..
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerPrincipalDisconnect {
+                        principal: &principal,
+                        reason: &reason,
+                    },
+                });
+
+                let sessions = sessions.read()?;
+                for s in connections.iter().filter_map(|v| sessions.get(v)) {
+                    s.1.do_send(crate::messages::WsMessage::Disconnect(reason.clone()));
+                }
+                Ok(())
+            },
+            // Handles messages for connections this instance owns.
+            | hydrogen_bus::redis::Message::S2CMessage {
+                connection,
+                time: _,
+                content_type,
+                message,
+                reply_to,
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerMessagePost {
+                        connection: &connection,
+                    },
+                });
+                let message = crate::messages::Payload::from_bus(content_type, message)?;
+                match sessions.read()?.get(&connection) {
+                    | Some(s) => {
+                        s.1.do_send(crate::messages::WsMessage::Message {
+                            endpoint: s.0.to_owned(),
+                            message,
+                            reply_to,
+                        });
+                        Ok(())
+                    },
+                    | None => {
+                        if let Some(reply_to) = reply_to {
+                            redis::cmd("PUBLISH")
+                                .arg(reply_to)
+                                .arg(serde_json::to_string(&hydrogen_bus::redis::Reply::ConnectionNotFound)?)
+                                .query::<()>(&mut redis_conn.get_connection()?)?;
+                        }
+                        Err(Box::new(crate::error::ConnectionNotFoundError::new(
+                            &connection.to_string(),
+                        )))
+                    },
+                }
+            },
+            // Handles server disconnect requests for a connection this instance owns..
+            | hydrogen_bus::redis::Message::SDisconnect {
+                connection,
+                time: _,
+                reason,
+            } => {
+                crate::logger::LogMessage::now(thread_instance_id, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerDisconnect {
+                        connection: &connection,
+                        reason: &reason,
+                    },
+                });
+                match sessions.read()?.get(&connection) {
+                    | Some(s) => {
+                        s.1.do_send(crate::messages::WsMessage::Disconnect(reason));
+                        Ok(())
+                    },
+                    | None => Err(Box::new(crate::error::ConnectionNotFoundError::new(
+                        &connection.to_string(),
+                    ))),
+                }
+            },
+        }
+    }
+
     fn start_stats_reporting_thread(
         instance_id: String,
         interval: u64,
@@ -217,337 +473,1266 @@ impl Server {
         })
     }
 
-    fn invoke_connect_route(
-        &self,
-        endpoint: &str,
-        headers: &std::collections::HashMap<String, String>,
-        message: &crate::messages::Connect,
-    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let mut req = ureq::post(endpoint);
-        for (k, v) in headers.iter() {
-            req = req.set(k, v);
+    async fn invoke_connect_route(
+        http: awc::Client,
+        instance: String,
+        route: crate::config::ConnectRoute,
+        request: crate::routes::ConnectRequest,
+        trace: opentelemetry::Context,
+    ) -> std::result::Result<crate::routes::ConnectResponse, Box<dyn std::error::Error>> {
+        let cx = hydrogen_telemetry::start("connect", opentelemetry::trace::SpanKind::Client, &trace, vec![
+            opentelemetry::KeyValue::new("hydrogen.connection_id", request.connection_id.clone()),
+        ]);
+        let mut req = http.post(&route.endpoint);
+        for (k, v) in route.headers.iter().chain(hydrogen_telemetry::inject(&cx).iter()) {
+            req = req.insert_header((k.as_str(), v.as_str()));
         }
 
-        let resp = req.send_string(&serde_json::to_string(&crate::routes::ConnectRequest {
-            instance_id: self.instance.clone(),
-            group_id: self.config.group_id.clone(),
-            endpoint: message.endpoint.clone(),
-            connection_id: message.connection.clone(),
-            time: message.time.clone(),
-        })?)?;
+        let started = std::time::Instant::now();
+        let res = req.send_json(&request).await;
+        crate::metrics::observe_route("connect", started, res.as_ref().ok().map(|v| v.status().as_u16()));
+        let mut resp = res.inspect_err(|e| hydrogen_telemetry::record_error(&cx, e))?;
+        hydrogen_telemetry::record_status(&cx, resp.status().as_u16());
 
-        crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
+        crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
             data: crate::logger::Event::ConnectRouteResponse {
-                connection: &message.connection,
-                response: resp.status(),
+                connection: &request.connection_id,
+                response: resp.status().as_u16(),
             },
         });
 
-        match resp.status() {
-            | 200 => Ok(()),
+        match resp.status().as_u16() {
+            // the response body is optional
+            | 200 => match resp.content_type() {
+                | "application/json" => Ok(resp.json::<crate::routes::ConnectResponse>().await?),
+                | _ => Ok(crate::routes::ConnectResponse::default()),
+            },
             | _ => Err(Box::new(crate::error::ConnectRouteError::new(&format!(
                 "connect route error code {}",
-                resp.status()
+                resp.status().as_u16()
             )))),
         }
     }
 
-    fn invoke_disconnect_route(
-        &self,
-        endpoint: &str,
-        headers: &std::collections::HashMap<String, String>,
-        message: &crate::messages::Disconnect,
+    async fn invoke_disconnect_route(
+        http: awc::Client,
+        instance: String,
+        route: crate::config::DisconnectRoute,
+        request: crate::routes::DisconnectRequest,
+        trace: opentelemetry::Context,
     ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let mut req = ureq::post(endpoint);
-        for (k, v) in headers.iter() {
-            req = req.set(k, v);
+        let cx = hydrogen_telemetry::start("disconnect", opentelemetry::trace::SpanKind::Client, &trace, vec![
+            opentelemetry::KeyValue::new("hydrogen.connection_id", request.connection_id.clone()),
+        ]);
+        let mut req = http.post(&route.endpoint);
+        for (k, v) in route.headers.iter().chain(hydrogen_telemetry::inject(&cx).iter()) {
+            req = req.insert_header((k.as_str(), v.as_str()));
         }
 
-        let resp = req.send_string(&serde_json::to_string(&crate::routes::DisconnectRequest {
-            instance_id: self.instance.clone(),
-            group_id: self.config.group_id.clone(),
-            endpoint: message.endpoint.clone(),
-            connection_id: message.connection.clone(),
-            time: message.time.clone(),
-        })?)?;
+        let started = std::time::Instant::now();
+        let res = req.send_json(&request).await;
+        crate::metrics::observe_route("disconnect", started, res.as_ref().ok().map(|v| v.status().as_u16()));
+        let resp = res.inspect_err(|e| hydrogen_telemetry::record_error(&cx, e))?;
+        hydrogen_telemetry::record_status(&cx, resp.status().as_u16());
 
-        crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
+        crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
             data: crate::logger::Event::DisconnectRouteResponse {
-                connection: &message.connection,
-                response: resp.status(),
+                connection: &request.connection_id,
+                response: resp.status().as_u16(),
             },
         });
 
-        match resp.status() {
+        match resp.status().as_u16() {
             | 200 => Ok(()),
             | _ => Err(Box::new(crate::error::DisconnectRouteError::new(&format!(
                 "disconnect route error code {}",
-                resp.status()
+                resp.status().as_u16()
             )))),
         }
     }
+
+    /// Removes the connection from the given admission counts.
+    async fn release_admission(
+        redis: &mut ConnectionManager,
+        admission_keys: &[String],
+        connection: &str,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut pipe = redis::pipe();
+        for admission_key in admission_keys.iter() {
+            pipe.cmd("ZREM").arg(admission_key).arg(connection).ignore();
+        }
+        Ok(pipe.query_async::<_, ()>(redis).await?)
+    }
+
+    /// Looks up the instance owning the given connection. Returns `None` if
+    /// the connection is unknown.
+    async fn lookup_owner(
+        redis: &mut ConnectionManager,
+        reverse_key: &str,
+    ) -> std::result::Result<std::option::Option<String>, Box<dyn std::error::Error>> {
+        Ok(redis::cmd("GET")
+            .arg(reverse_key)
+            .query_async::<_, std::option::Option<String>>(redis)
+            .await?)
+    }
+
+    /// Adds the connection to the given rooms.
+    async fn join_rooms(
+        redis: &mut ConnectionManager,
+        room_prefix: &str,
+        rooms_key: &str,
+        connection: &str,
+        rooms: &[String],
+        ttl: u16,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        if rooms.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for room in rooms.iter() {
+            let room_key = format!("{}{}", room_prefix, room);
+            pipe.sadd(&room_key, connection)
+                .ignore()
+                .expire(&room_key, ttl.into())
+                .ignore();
+        }
+        pipe.sadd(rooms_key, rooms)
+            .ignore()
+            .expire(rooms_key, ttl.into())
+            .ignore()
+            .query_async::<_, ()>(redis)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes the connection from the given rooms.
+    async fn leave_rooms(
+        redis: &mut ConnectionManager,
+        room_prefix: &str,
+        rooms_key: &str,
+        connection: &str,
+        rooms: &[String],
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        if rooms.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for room in rooms.iter() {
+            pipe.srem(format!("{}{}", room_prefix, room), connection).ignore();
+        }
+        pipe.srem(rooms_key, rooms).ignore().query_async::<_, ()>(redis).await?;
+        Ok(())
+    }
+
+    /// Resolves the owning instances of the connections in the given set and
+    /// returns the connections grouped by instance. Connections that are gone
+    /// are removed from the set.
+    async fn lookup_owners(
+        redis: &mut ConnectionManager,
+        set_key: &str,
+        reverse_prefix: &str,
+    ) -> std::result::Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+        let mut by_owner = HashMap::<String, Vec<String>>::new();
+        let members = redis::cmd("SMEMBERS")
+            .arg(set_key)
+            .query_async::<_, Vec<String>>(redis)
+            .await?;
+        if members.is_empty() {
+            return Ok(by_owner);
+        }
+
+        let mut pipe = redis::pipe();
+        for member in members.iter() {
+            pipe.cmd("GET").arg(format!("{}{}", reverse_prefix, member));
+        }
+        let owners = pipe.query_async::<_, Vec<std::option::Option<String>>>(redis).await?;
+
+        let mut gone = Vec::new();
+        for (member, owner) in members.into_iter().zip(owners) {
+            match owner {
+                | Some(v) => by_owner.entry(v).or_default().push(member),
+                | None => gone.push(member),
+            }
+        }
+        if !gone.is_empty() {
+            redis::cmd("SREM")
+                .arg(set_key)
+                .arg(&gone)
+                .query_async::<_, ()>(redis)
+                .await?;
+        }
+        Ok(by_owner)
+    }
+
+    /// Publishes the message into the redis pub/sub channel of the given
+    /// instance.
+    async fn publish_to_instance(
+        redis: &mut ConnectionManager,
+        instance: &str,
+        message: &hydrogen_bus::redis::Message,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let res = redis::pipe()
+            .publish(
+                hydrogen_bus::channels::instance(instance),
+                serde_json::to_string(message)?,
+            )
+            .query_async::<_, ()>(redis)
+            .await;
+        if res.is_err() {
+            crate::metrics::REDIS_PUBLISH_FAILURES.inc();
+        }
+        Ok(res?)
+    }
+
+    /// Runs a dependency check bounded by `PROBE_TIMEOUT`.
+    async fn probe(check: impl std::future::Future<Output=std::result::Result<(), String>>) -> Check {
+        match actix_web::rt::time::timeout(PROBE_TIMEOUT, check).await {
+            | Ok(Ok(())) => Check::up(),
+            | Ok(Err(e)) => Check::down(&e),
+            | Err(..) => Check::down("timed out"),
+        }
+    }
+
+    /// Logs the error of a failed handler future.
+    fn log_error(&self, err: &dyn std::error::Error) {
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::Error { err: &err.to_string() },
+        });
+    }
 }
 
 impl Actor for Server {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(resubscribed) = self.resubscribed.take() {
+            ctx.add_stream(resubscribed);
+        }
+    }
+}
+
+/// Handler for re-established redis subscriptions.
+impl StreamHandler<Resubscribed> for Server {
+    /// This function will register the connections of this instance in redis
+    /// again, in case redis lost them while it was unavailable. Room
+    /// memberships are not restored.
+    fn handle(&mut self, _: Resubscribed, ctx: &mut Context<Self>) {
+        let ttl = self.config.redis.mapping_ttl_sec;
+        let now = chrono::Utc::now().to_rfc3339();
+        let mut pipe = redis::pipe();
+        for (connection, registration) in self.registrations.iter() {
+            let key = self.make_key(connection);
+            let rkey = self.make_reverse_key(connection);
+            pipe.hset_multiple(&key, &registration.info)
+                .ignore()
+                .hset(&key, "last_heartbeat", &now)
+                .ignore()
+                .cmd("EXPIRE")
+                .arg(&key)
+                .arg(ttl)
+                .ignore()
+                .cmd("SET")
+                .arg(&rkey)
+                .arg(&self.instance)
+                .ignore()
+                .cmd("EXPIRE")
+                .arg(&rkey)
+                .arg(ttl)
+                .ignore();
+            if let Some(principal) = &registration.principal {
+                let principal_key = self.make_principal_key(principal);
+                pipe.cmd("SADD")
+                    .arg(&principal_key)
+                    .arg(connection)
+                    .ignore()
+                    .cmd("EXPIRE")
+                    .arg(&principal_key)
+                    .arg(ttl)
+                    .ignore();
+            }
+        }
+        let count = self.registrations.len();
+        let mut redis = self.redis.clone();
+
+        let fut = async move { pipe.query_async::<_, ()>(&mut redis).await };
+        ctx.spawn(fut.into_actor(self).map(move |res, act, _| match res {
+            | Ok(_) => crate::logger::LogMessage::now(&act.instance, crate::logger::Data::Event {
+                data: crate::logger::Event::RedisResubscribed { connections: count },
+            }),
+            | Err(e) => act.log_error(&e),
+        }));
+    }
+
+    // the stream only ends with the redis thread
+    fn finished(&mut self, _: &mut Context<Self>) {}
+}
+
+/// Handler for the health check of the redis subscription.
+impl Handler<IsSubscribed> for Server {
+    type Result = bool;
+
+    fn handle(&mut self, _: IsSubscribed, _: &mut Context<Self>) -> Self::Result {
+        self.subscribed.load(Ordering::SeqCst)
+    }
+}
+
+/// Handler for the liveness probe, it is only answered while the server
+/// processes its mailbox.
+impl Handler<IsAlive> for Server {
+    type Result = bool;
+
+    fn handle(&mut self, _: IsAlive, _: &mut Context<Self>) -> Self::Result {
+        !self.redis_thread.is_finished()
+    }
+}
+
+/// Handler for the dependency checks of the readiness probe.
+impl Handler<CheckDependencies> for Server {
+    type Result = ResponseActFuture<Self, BTreeMap<String, Check>>;
+
+    /// This function will ping redis and request the account info of every
+    /// JetStream the instance publishes client messages to concurrently.
+    fn handle(&mut self, _: CheckDependencies, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut redis = self.redis.clone();
+        let subscription = match self.subscribed.load(Ordering::SeqCst) {
+            | true => Check::up(),
+            | false => Check::down("not subscribed"),
+        };
+        let streams = self
+            .nats_js
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let fut = async move {
+            let ping = Self::probe(async move {
+                redis::cmd("PING")
+                    .query_async::<_, String>(&mut redis)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            });
+            let jetstream = futures::future::join_all(streams.into_iter().map(|(endpoint, js)| async move {
+                let check = Self::probe(async move {
+                    match actix_web::rt::task::spawn_blocking(move || js.account_info()).await {
+                        | Ok(Ok(..)) => Ok(()),
+                        | Ok(Err(e)) => Err(e.to_string()),
+                        | Err(e) => Err(e.to_string()),
+                    }
+                })
+                .await;
+                (format!("jetstream@{}", endpoint), check)
+            }));
+            let (redis, jetstream) = futures::future::join(ping, jetstream).await;
+
+            let mut checks = BTreeMap::new();
+            checks.insert("redis".to_owned(), redis);
+            checks.insert("redis_subscription".to_owned(), subscription);
+            checks.extend(jetstream);
+            checks
+        };
+        Box::pin(fut.into_actor(self))
+    }
+}
+
+/// Atomically drops expired connections from the admission counts, checks
+/// the limits and counts the connection if all of them pass.
+/// `KEYS`: the counts, `ARGV`: now, expiry, connection, ttl and the limit of
+/// every count.
+const ADMIT_SCRIPT: &str = r#"
+for i, key in ipairs(KEYS) do
+    redis.call('ZREMRANGEBYSCORE', key, '-inf', ARGV[1])
+    if redis.call('ZCARD', key) >= tonumber(ARGV[i + 4]) then
+        return 0
+    end
+end
+for _, key in ipairs(KEYS) do
+    redis.call('ZADD', key, ARGV[2], ARGV[3])
+    redis.call('EXPIRE', key, ARGV[4])
+end
+return 1
+"#;
+
+/// Handler for admitting a new connection against the limits of the group.
+impl Handler<Admit> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<bool, u16>>;
+
+    /// This function will count the connection in redis unless the limits on
+    /// the connections to its endpoint or of its principal are reached.
+    fn handle(&mut self, msg: Admit, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut limits = Vec::new();
+        if let Some(max) = msg.max_endpoint_connections {
+            limits.push((self.make_admission_endpoint_key(&msg.endpoint), max));
+        }
+        if let (Some(max), Some(principal)) = (msg.max_principal_connections, &msg.principal) {
+            limits.push((self.make_admission_principal_key(principal), max));
+        }
+        let ttl = self.config.redis.mapping_ttl_sec;
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            if limits.is_empty() {
+                return Ok(true);
+            }
+            let now = chrono::Utc::now().timestamp();
+            let script = redis::Script::new(ADMIT_SCRIPT);
+            let mut invocation = script.prepare_invoke();
+            for (key, _) in limits.iter() {
+                invocation.key(key);
+            }
+            invocation
+                .arg(now)
+                .arg(now + i64::from(ttl))
+                .arg(&msg.connection)
+                .arg(ttl);
+            for (_, max) in limits.iter() {
+                invocation.arg(*max);
+            }
+            Ok(invocation.invoke_async::<_, i64>(&mut redis).await? == 1)
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => Ok(v),
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
+
+/// Handler for releasing the admission of a connection that failed to start.
+impl Handler<Release> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
+
+    /// This function will remove the connection from the admission counts of
+    /// its endpoint and principal in redis.
+    fn handle(&mut self, msg: Release, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut admission_keys = vec![self.make_admission_endpoint_key(&msg.endpoint)];
+        admission_keys.extend(msg.principal.as_ref().map(|v| self.make_admission_principal_key(v)));
+        let mut redis = self.redis.clone();
+
+        let fut = async move { Self::release_admission(&mut redis, &admission_keys, &msg.connection).await };
+        Box::pin(fut.into_actor(self).map(|res, act, _| match res {
+            | Ok(_) => Ok(()),
+            | Err(e) => {
+                act.log_error(e.as_ref());
+                Err(500_u16)
+            },
+        }))
+    }
 }
 
 /// Handler for the OnConnect event in which a client has been permitted for a
 /// server connection and is now establishing the connection.
 impl Handler<Connect> for Server {
-    type Result = std::result::Result<(), u16>;
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
 
     /// This function will create a client/server map in redis for the
-    /// connection (id) and this instance (id). It will also invoke the
-    /// connect route if specified.
+    /// connection (id) and this instance (id) and index the connection by its
+    /// principal if there is one. It will also invoke the
+    /// connect route if specified and join the rooms that were assigned by
+    /// the authorizer or the connect route.
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                data: crate::logger::Event::Connect {
-                    connection: &msg.connection.to_string(),
-                },
-            });
-            self.sessions
-                .write()
-                .unwrap()
-                .insert(msg.connection.clone(), (msg.endpoint.clone(), msg.addr.clone())); // must never be poisoned
-            let key = self.make_key(&msg.connection);
-            let rkey = self.make_reverse_key(&msg.connection);
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::Connect {
+                connection: &msg.connection,
+            },
+        });
+        crate::metrics::CONNECTIONS.with_label_values(&[&msg.endpoint]).inc();
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(msg.connection.clone(), (msg.endpoint.clone(), msg.addr.clone())); // must never be poisoned
+
+        let key = self.make_key(&msg.connection);
+        let rkey = self.make_reverse_key(&msg.connection);
+        let ttl = self.config.redis.mapping_ttl_sec;
+        let mut redis = self.redis.clone();
+        let http = self.http.clone();
+        let instance = self.instance.clone();
+        let route = self.config.routes.connect_for(&msg.endpoint).cloned();
+        let room_prefix = self.make_room_key_prefix();
+        let rooms_key = self.make_rooms_key(&msg.connection);
+        let mut rooms = msg.rooms.clone();
+        let connection = msg.connection.clone();
+        let principal_key = msg.context.principal.as_ref().map(|v| self.make_principal_key(v));
+        let trace = msg.trace.clone();
+        let request = crate::routes::ConnectRequest {
+            instance_id: self.instance.clone(),
+            group_id: self.config.group_id.clone(),
+            endpoint: msg.endpoint.clone(),
+            connection_id: msg.connection.clone(),
+            time: msg.time.clone(),
+        };
 
+        let info = [
+            ("instance_id", self.instance.clone()),
+            ("endpoint", msg.endpoint.clone()),
+            ("connected_at", msg.time.clone()),
+            ("last_heartbeat", msg.time.clone()),
+            ("context", serde_json::to_string(&msg.context).unwrap_or_default()),
+        ];
+        self.registrations.insert(msg.connection.clone(), Registration {
+            info: info.clone(),
+            principal: msg.context.principal.clone(),
+        });
+
+        let fut = async move {
             redis::pipe()
-                .cmd("SET")
-                .arg(&key)
-                .arg(1)
+                .hset_multiple(&key, &info)
+                .ignore()
                 .cmd("EXPIRE")
                 .arg(&key)
-                .arg(30)
+                .arg(ttl)
                 .cmd("SET")
                 .arg(&rkey)
-                .arg(&self.instance.to_string())
+                .arg(&instance)
                 .cmd("EXPIRE")
                 .arg(&rkey)
-                .arg(30)
-                .query::<()>(&mut self.redis.get_connection()?)?;
-
-            match &self.config.routes.connect {
-                | Some(c) => self.invoke_connect_route(&c.endpoint, &c.headers, &msg),
-                | None => Ok(()),
-            }?;
+                .arg(ttl)
+                .query_async::<_, ()>(&mut redis)
+                .await?;
+            if let Some(principal_key) = principal_key {
+                redis::pipe()
+                    .cmd("SADD")
+                    .arg(&principal_key)
+                    .arg(&connection)
+                    .ignore()
+                    .cmd("EXPIRE")
+                    .arg(&principal_key)
+                    .arg(ttl)
+                    .query_async::<_, ()>(&mut redis)
+                    .await?;
+            }
 
-            Ok(())
+            if let Some(c) = route {
+                rooms.extend(
+                    Self::invoke_connect_route(http, instance, c, request, trace)
+                        .await?
+                        .rooms,
+                );
+            }
+            Self::join_rooms(&mut redis, &room_prefix, &rooms_key, &connection, &rooms, ttl).await
         };
-        match safecall() {
+        Box::pin(fut.into_actor(self).map(move |res, act, _| match res {
             | Ok(_) => Ok(()),
             | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
-                });
-                self.sessions.write().unwrap().remove(&msg.connection); // must never be poisoned
+                act.log_error(e.as_ref());
+                act.sessions.write().unwrap().remove(&msg.connection); // must never be poisoned
+                act.registrations.remove(&msg.connection);
                 Err(500_u16)
             },
-        }
+        }))
     }
 }
 
 /// Handler for disconnect events which occurr when a client or the server ends
 /// the connection.
 impl Handler<Disconnect> for Server {
-    type Result = std::result::Result<(), u16>;
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
 
-    /// This function will purge the redis client/server mappings invoke the
-    /// disconnect route if specified.
+    /// This function will purge the redis client/server mappings as well as
+    /// the room memberships, the principal index and the admission counts and
+    /// invoke the disconnect route if specified.
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                data: crate::logger::Event::Disconnect {
-                    connection: &msg.connection.to_string(),
-                },
-            });
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::Disconnect {
+                connection: &msg.connection,
+            },
+        });
+        crate::metrics::CONNECTIONS.with_label_values(&[&msg.endpoint]).dec();
+        self.sessions.write().unwrap().remove(&msg.connection); // must never be poisoned
+        self.registrations.remove(&msg.connection);
+        self.pending_disconnects += 1;
+
+        let key = self.make_key(&msg.connection);
+        let rkey = self.make_reverse_key(&msg.connection);
+        let mut redis = self.redis.clone();
+        let http = self.http.clone();
+        let instance = self.instance.clone();
+        let route = self.config.routes.disconnect_for(&msg.endpoint).cloned();
+        let room_prefix = self.make_room_key_prefix();
+        let rooms_key = self.make_rooms_key(&msg.connection);
+        let connection = msg.connection.clone();
+        let principal_key = msg.principal.as_ref().map(|v| self.make_principal_key(v));
+        let mut admission_keys = vec![self.make_admission_endpoint_key(&msg.endpoint)];
+        admission_keys.extend(msg.principal.as_ref().map(|v| self.make_admission_principal_key(v)));
+        let trace = msg.trace;
+        let request = crate::routes::DisconnectRequest {
+            instance_id: self.instance.clone(),
+            group_id: self.config.group_id.clone(),
+            endpoint: msg.endpoint.clone(),
+            connection_id: msg.connection.clone(),
+            time: msg.time.clone(),
+        };
 
-            let key = self.make_key(&msg.connection);
-            let rkey = self.make_reverse_key(&msg.connection);
+        let fut = async move {
             redis::pipe()
                 .cmd("DEL")
                 .arg(&key)
                 .cmd("DEL")
                 .arg(&rkey)
-                .query::<()>(&mut self.redis.get_connection()?)?;
-
-            self.sessions.write().unwrap().remove(&msg.connection); // must never be poisoned
+                .query_async::<_, ()>(&mut redis)
+                .await?;
+            let rooms = redis::cmd("SMEMBERS")
+                .arg(&rooms_key)
+                .query_async::<_, Vec<String>>(&mut redis)
+                .await?;
+            Self::leave_rooms(&mut redis, &room_prefix, &rooms_key, &connection, &rooms).await?;
+            if let Some(principal_key) = principal_key {
+                redis::cmd("SREM")
+                    .arg(&principal_key)
+                    .arg(&connection)
+                    .query_async::<_, ()>(&mut redis)
+                    .await?;
+            }
+            Self::release_admission(&mut redis, &admission_keys, &connection).await?;
 
-            match &self.config.routes.disconnect {
-                | Some(c) => self.invoke_disconnect_route(&c.endpoint, &c.headers, &msg),
+            match route {
+                | Some(c) => Self::invoke_disconnect_route(http, instance, c, request, trace).await,
                 | None => Ok(()),
             }
         };
-        match safecall() {
-            | Ok(_) => Ok(()),
-            | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
-                });
-                Err(500_u16)
-            },
+        Box::pin(fut.into_actor(self).map(|res, act, _| {
+            act.pending_disconnects -= 1;
+            match res {
+                | Ok(_) => Ok(()),
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            }
+        }))
+    }
+}
+
+/// Handler for draining the connections of this instance on shutdown.
+impl Handler<Drain> for Server {
+    type Result = ();
+
+    /// This function will close every connection at a random point within the
+    /// drain window so that the clients do not reconnect all at once. The
+    /// connections run the regular disconnect flow once they are closed.
+    fn handle(&mut self, msg: Drain, ctx: &mut Context<Self>) -> Self::Result {
+        let mut rng = rand::thread_rng();
+        let sessions = self.sessions.read().unwrap(); // must never be poisoned
+        for (_, socket) in sessions.values() {
+            let delay = if msg.window.is_zero() {
+                std::time::Duration::ZERO
+            } else {
+                rng.gen_range(std::time::Duration::ZERO..msg.window)
+            };
+            let (socket, code, reason) = (socket.clone(), msg.code, msg.reason.clone());
+            ctx.run_later(delay, move |_, _| {
+                socket.do_send(crate::messages::WsMessage::Close { code, reason })
+            });
         }
     }
 }
 
+/// Handler for checking whether draining is complete.
+impl Handler<PendingSessions> for Server {
+    type Result = usize;
+
+    fn handle(&mut self, _: PendingSessions, _: &mut Context<Self>) -> Self::Result {
+        self.sessions.read().unwrap().len() + self.pending_disconnects // must never be poisoned
+    }
+}
+
+/// Records the heartbeat on the instance-to-client mapping and renews its
+/// expiry unless the mapping has expired already.
+/// `KEYS`: the mapping, `ARGV`: the time of the heartbeat and ttl.
+const HEARTBEAT_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'last_heartbeat', ARGV[1])
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return 1
+"#;
+
 /// Handler for heartbeat messages on a connection. Heartbeats are used to
 /// ping/pong whether a connection is still established and a client still
 /// active. It also helps to prevent timeouts for connections that are
 /// established but do not see any message for a certain perdiod of time.
 impl Handler<Heartbeat> for Server {
-    type Result = std::result::Result<(), u16>;
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
 
-    /// This function will update the expiry time on the client-to-server as
-    /// well as server-to-client mappings in redis.
+    /// This function will renew the expiry time (`redis.mapping_ttl_sec`) on
+    /// the client-to-server as well as server-to-client mappings, the rooms
+    /// and principal of the connection and the admission counts in redis and
+    /// record the time of the heartbeat. Mappings that have expired already are
+    /// not recreated.
     fn handle(&mut self, msg: Heartbeat, _ctx: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            let key = self.make_key(&msg.connection);
-            let rkey = self.make_reverse_key(&msg.connection);
-            redis::pipe()
-                .cmd("EXPIRE")
-                .arg(&key)
-                .arg(30)
-                .cmd("EXPIRE")
+        let key = self.make_key(&msg.connection);
+        let rkey = self.make_reverse_key(&msg.connection);
+        let rooms_key = self.make_rooms_key(&msg.connection);
+        let room_prefix = self.make_room_key_prefix();
+        let principal_key = msg.principal.as_ref().map(|v| self.make_principal_key(v));
+        let mut admission_keys = vec![self.make_admission_endpoint_key(&msg.endpoint)];
+        admission_keys.extend(msg.principal.as_ref().map(|v| self.make_admission_principal_key(v)));
+        let ttl = self.config.redis.mapping_ttl_sec;
+        let expiry = chrono::Utc::now().timestamp() + i64::from(ttl);
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let rooms = redis::cmd("SMEMBERS")
+                .arg(&rooms_key)
+                .query_async::<_, Vec<String>>(&mut redis)
+                .await?;
+            let mut pipe = redis::pipe();
+            // the sets live as long as any of their members
+            for room in rooms.iter() {
+                pipe.cmd("EXPIRE")
+                    .arg(format!("{}{}", room_prefix, room))
+                    .arg(ttl)
+                    .ignore();
+            }
+            if let Some(principal_key) = &principal_key {
+                pipe.cmd("EXPIRE").arg(principal_key).arg(ttl).ignore();
+            }
+            // only connections that have been admitted are counted
+            for admission_key in admission_keys.iter() {
+                pipe.cmd("ZADD")
+                    .arg(admission_key)
+                    .arg("XX")
+                    .arg(expiry)
+                    .arg(&msg.connection)
+                    .ignore()
+                    .cmd("EXPIRE")
+                    .arg(admission_key)
+                    .arg(ttl)
+                    .ignore();
+            }
+            pipe.cmd("EXPIRE")
                 .arg(&rkey)
-                .arg(30)
-                .query::<()>(&mut self.redis.get_connection()?)?;
-            Ok(())
+                .arg(ttl)
+                .cmd("EXPIRE")
+                .arg(&rooms_key)
+                .arg(ttl)
+                .query_async::<_, ()>(&mut redis)
+                .await?;
+            // an expired mapping must not be recreated without its details
+            redis::Script::new(HEARTBEAT_SCRIPT)
+                .key(&key)
+                .arg(&msg.time)
+                .arg(ttl)
+                .invoke_async::<_, ()>(&mut redis)
+                .await
         };
-        match safecall() {
+        Box::pin(fut.into_actor(self).map(|res, act, _| match res {
             | Ok(_) => Ok(()),
             | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
-                });
+                act.log_error(&e);
                 Err(500_u16)
             },
-        }
+        }))
     }
 }
 
-/// Handler for messages that are sent from this server towards any client.
-impl Handler<ServerMessage> for Server {
-    type Result = ();
+/// Handler for looking up a single connection in the group.
+impl Handler<GetConnection> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<ConnectionInfo, u16>>;
 
-    /// This function will take the message and the specified connection, lookup
-    /// the instance of the gateway that holds the specified client
-    /// connection and post the message into the corresponding redis pub/sub
-    /// channel.
-    fn handle(&mut self, msg: ServerMessage, _ctx: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                data: crate::logger::Event::ServerMessageEnqueue {
-                    connection: &msg.connection.to_string(),
+    /// This function will resolve the owning instance of the connection and
+    /// read the connection details it registered in redis.
+    fn handle(&mut self, msg: GetConnection, _ctx: &mut Context<Self>) -> Self::Result {
+        let rkey = self.make_reverse_key(&msg.connection);
+        let prefix = self.make_key_prefix();
+        let instance = self.instance.clone();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let owner = match Self::lookup_owner(&mut redis, &rkey).await? {
+                | Some(v) => v,
+                | None => return Ok(Err(404_u16)),
+            };
+            let info = redis::cmd("HGETALL")
+                .arg(format!("{}{}:{}", prefix, owner, msg.connection))
+                .query_async::<_, HashMap<String, String>>(&mut redis)
+                .await?;
+            if info.is_empty() {
+                return Ok(Err(404_u16));
+            }
+            match ConnectionInfo::from_hash(&msg.connection, info) {
+                | Ok(v) => Ok(Ok(v)),
+                | Err(e) => {
+                    // a malformed connection is as good as an unknown one
+                    crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
+                        data: crate::logger::Event::Error {
+                            err: &format!("malformed connection {}: {}", msg.connection, e),
+                        },
+                    });
+                    Ok(Err(404_u16))
                 },
-            });
+            }
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
 
-            let conn = msg.connection.clone();
-            let redis_message: hydrogen_bus::redis::Message = msg.into();
+/// Handler for listing the connections in the group page by page.
+impl Handler<ListConnections> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<ConnectionPage, u16>>;
 
-            let target_instance = redis::cmd("GET")
-                .arg(&self.make_reverse_key(&conn))
-                .query::<String>(&mut self.redis.get_connection()?)?;
-            redis::pipe()
-                .publish(
-                    format!("hydrogen:instance:{}", target_instance),
-                    serde_json::to_string(&redis_message)?,
-                )
-                .query::<()>(&mut self.redis.get_connection()?)?;
-            Ok(())
+    /// This function will run a single `SCAN` iteration over the
+    /// instance-to-client keys of the group and read the details of every
+    /// connection found, filtered by endpoint if specified. Malformed
+    /// connections are logged and left out.
+    fn handle(&mut self, msg: ListConnections, _ctx: &mut Context<Self>) -> Self::Result {
+        let prefix = self.make_key_prefix();
+        let instance = self.instance.clone();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let (cursor, keys) = redis::cmd("SCAN")
+                .arg(msg.cursor)
+                .arg("MATCH")
+                .arg(format!("{}*", prefix))
+                .arg("COUNT")
+                .arg(msg.count)
+                .query_async::<_, (u64, Vec<String>)>(&mut redis)
+                .await?;
+
+            let mut pipe = redis::pipe();
+            for key in keys.iter() {
+                pipe.cmd("HGETALL").arg(key);
+            }
+            let infos = pipe.query_async::<_, Vec<HashMap<String, String>>>(&mut redis).await?;
+
+            let mut connections = Vec::new();
+            for (key, info) in keys.iter().zip(infos) {
+                // connections may have expired in between
+                if info.is_empty() {
+                    continue;
+                }
+                let connection = key.rsplit(':').next().unwrap_or_default();
+                // malformed connections are skipped instead of failing the page
+                let info = match ConnectionInfo::from_hash(connection, info) {
+                    | Ok(v) => v,
+                    | Err(e) => {
+                        crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
+                            data: crate::logger::Event::Error {
+                                err: &format!("malformed connection {}: {}", connection, e),
+                            },
+                        });
+                        continue;
+                    },
+                };
+                if msg.endpoint.as_ref().is_none_or(|v| v == &info.endpoint) {
+                    connections.push(info);
+                }
+            }
+            Ok(Ok(ConnectionPage {
+                cursor: if cursor == 0 { None } else { Some(cursor) },
+                connections,
+            }))
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
+
+/// Handler for adding a connection anywhere in the group to a room.
+impl Handler<JoinRoom> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
+
+    /// This function will add the connection to the room if the connection is
+    /// known. The membership expires together with the connection mappings.
+    fn handle(&mut self, msg: JoinRoom, _ctx: &mut Context<Self>) -> Self::Result {
+        let rkey = self.make_reverse_key(&msg.connection);
+        let room_prefix = self.make_room_key_prefix();
+        let rooms_key = self.make_rooms_key(&msg.connection);
+        let ttl = self.config.redis.mapping_ttl_sec;
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            if Self::lookup_owner(&mut redis, &rkey).await?.is_none() {
+                return Ok(Err(404_u16));
+            }
+            Self::join_rooms(&mut redis, &room_prefix, &rooms_key, &msg.connection, &[msg.room], ttl).await?;
+            Ok(Ok(()))
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
+
+/// Handler for removing a connection anywhere in the group from a room.
+impl Handler<LeaveRoom> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
+
+    /// This function will remove the connection from the room if the
+    /// connection is known.
+    fn handle(&mut self, msg: LeaveRoom, _ctx: &mut Context<Self>) -> Self::Result {
+        let rkey = self.make_reverse_key(&msg.connection);
+        let room_prefix = self.make_room_key_prefix();
+        let rooms_key = self.make_rooms_key(&msg.connection);
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            if Self::lookup_owner(&mut redis, &rkey).await?.is_none() {
+                return Ok(Err(404_u16));
+            }
+            Self::leave_rooms(&mut redis, &room_prefix, &rooms_key, &msg.connection, &[msg.room]).await?;
+            Ok(Ok(()))
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
+
+/// Handler for looking up the members of a room.
+impl Handler<RoomMembers> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<Vec<String>, u16>>;
+
+    /// This function will list the members of the room that are still
+    /// connected, the ones that are gone are removed from the room.
+    fn handle(&mut self, msg: RoomMembers, _ctx: &mut Context<Self>) -> Self::Result {
+        let room_key = self.make_room_key(&msg.room);
+        let reverse_prefix = self.make_reverse_key_prefix();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let by_owner = Self::lookup_owners(&mut redis, &room_key, &reverse_prefix).await?;
+            let mut members = by_owner.into_values().flatten().collect::<Vec<_>>();
+            members.sort();
+            Ok(members)
         };
-        match safecall() {
-            | Ok(_) => {},
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => Ok(v),
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
+
+/// Handler for messages that are sent from this server towards all members of
+/// a room.
+impl Handler<RoomServerMessage> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
+
+    /// This function will lookup the instances that hold the members of the
+    /// room and post the message into the redis pub/sub channels of only
+    /// these instances. Members whose connection is gone are removed from the
+    /// room.
+    fn handle(&mut self, msg: RoomServerMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ServerRoomMessageEnqueue { room: &msg.room },
+        });
+
+        let room_key = self.make_room_key(&msg.room);
+        let reverse_prefix = self.make_reverse_key_prefix();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let by_owner = Self::lookup_owners(&mut redis, &room_key, &reverse_prefix).await?;
+            for (owner, connections) in by_owner.into_iter() {
+                Self::publish_to_instance(&mut redis, &owner, &msg.to_bus(connections)).await?;
+            }
+            std::result::Result::<(), Box<dyn std::error::Error>>::Ok(())
+        };
+        Box::pin(fut.into_actor(self).map(|res, act, _| match res {
+            | Ok(_) => Ok(()),
             | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
-                });
+                act.log_error(e.as_ref());
+                Err(500_u16)
             },
-        }
+        }))
+    }
+}
+
+/// Handler for messages that are sent from this server towards all
+/// connections of a principal.
+impl Handler<PrincipalServerMessage> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
+
+    /// This function will lookup the instances that hold connections of the
+    /// principal and post the message into the redis pub/sub channels of only
+    /// these instances.
+    fn handle(&mut self, msg: PrincipalServerMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ServerPrincipalMessageEnqueue {
+                principal: &msg.principal,
+            },
+        });
+
+        let principal_key = self.make_principal_key(&msg.principal);
+        let reverse_prefix = self.make_reverse_key_prefix();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let by_owner = Self::lookup_owners(&mut redis, &principal_key, &reverse_prefix).await?;
+            if by_owner.is_empty() {
+                return Ok(Err(404_u16));
+            }
+            for (owner, connections) in by_owner.into_iter() {
+                Self::publish_to_instance(&mut redis, &owner, &msg.to_bus(connections)).await?;
+            }
+            Ok(Ok(()))
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
+
+/// Handler for the event in which the server needs to end all connections of a
+/// principal.
+impl Handler<PrincipalDisconnect> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
+
+    /// This function will lookup the instances that hold connections of the
+    /// principal and post a disconnect request for these connections to each
+    /// of them.
+    fn handle(&mut self, msg: PrincipalDisconnect, _ctx: &mut Context<Self>) -> Self::Result {
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ServerPrincipalDisconnect {
+                principal: &msg.principal,
+                reason: &msg.reason,
+            },
+        });
+
+        let principal_key = self.make_principal_key(&msg.principal);
+        let reverse_prefix = self.make_reverse_key_prefix();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let by_owner = Self::lookup_owners(&mut redis, &principal_key, &reverse_prefix).await?;
+            if by_owner.is_empty() {
+                return Ok(Err(404_u16));
+            }
+            for (owner, connections) in by_owner.into_iter() {
+                Self::publish_to_instance(&mut redis, &owner, &msg.to_bus(connections)).await?;
+            }
+            Ok(Ok(()))
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
     }
 }
 
 /// Handler for messages that are sent from this server towards any client.
-impl Handler<BroadcastServerMessage> for Server {
-    type Result = ();
+impl Handler<ServerMessage> for Server {
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
 
     /// This function will take the message and the specified connection, lookup
     /// the instance of the gateway that holds the specified client
     /// connection and post the message into the corresponding redis pub/sub
-    /// channel.
-    fn handle(&mut self, msg: BroadcastServerMessage, _ctx: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            match &msg {
-                | BroadcastServerMessage::All { .. } => {
-                    crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                        data: crate::logger::Event::ServerBroadcastMessageEnqueue {},
-                    });
+    /// channel. If an acknowledgement is requested, it will subscribe to a
+    /// unique reply channel beforehand and wait for the owning instance to
+    /// confirm the delivery.
+    fn handle(&mut self, msg: ServerMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ServerMessageEnqueue {
+                connection: &msg.connection,
+            },
+        });
+
+        let rkey = self.make_reverse_key(&msg.connection);
+        let reply_to = hydrogen_bus::channels::reply(&self.config.group_id, &Uuid::new_v4().to_string());
+        let client = self.redis_client.clone();
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let owner = match Self::lookup_owner(&mut redis, &rkey).await? {
+                | Some(v) => v,
+                | None => return Ok(Err(404_u16)),
+            };
+            let timeout = match msg.ack {
+                | Some(v) => v,
+                | None => {
+                    Self::publish_to_instance(&mut redis, &owner, &msg.into_bus(None)).await?;
+                    return Ok(Ok(()));
                 },
-                | BroadcastServerMessage::Endpoint { endpoint, .. } => {
-                    crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                        data: crate::logger::Event::ServerEndpointBroadcastMessageEnqueue { endpoint },
-                    });
+            };
+
+            let mut replies = client.get_async_connection().await?.into_pubsub();
+            replies.subscribe(&reply_to).await?;
+            Self::publish_to_instance(&mut redis, &owner, &msg.into_bus(Some(reply_to))).await?;
+            let reply = match actix_web::rt::time::timeout(timeout, replies.on_message().next()).await {
+                | Ok(Some(v)) => v,
+                | Ok(None) => {
+                    return Err(Box::<dyn std::error::Error>::from(crate::error::ReplyError::new(
+                        "reply channel closed",
+                    )))
                 },
+                | Err(_) => return Ok(Err(504_u16)),
+            };
+            match serde_json::from_str::<hydrogen_bus::redis::Reply>(&reply.get_payload::<String>()?)? {
+                | hydrogen_bus::redis::Reply::Delivered => Ok(Ok(())),
+                | hydrogen_bus::redis::Reply::ConnectionNotFound => Ok(Err(404_u16)),
+                | hydrogen_bus::redis::Reply::TooLarge => Ok(Err(413_u16)),
             }
+        };
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
+            },
+        ))
+    }
+}
 
-            let redis_message: hydrogen_bus::redis::Message = msg.into();
+/// Handler for replies on messages that have been processed by this instance.
+impl Handler<Reply> for Server {
+    type Result = ResponseActFuture<Self, ()>;
 
-            redis::pipe()
-                .publish(
-                    format!("hydrogen:group:{}:broadcast", self.config.group_id),
-                    serde_json::to_string(&redis_message)?,
-                )
-                .query::<()>(&mut self.redis.get_connection()?)?;
-            Ok(())
+    /// This function will publish the reply into the given reply channel.
+    fn handle(&mut self, msg: Reply, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let res = redis::pipe()
+                .publish(msg.reply_to, serde_json::to_string(&msg.reply)?)
+                .query_async::<_, ()>(&mut redis)
+                .await;
+            if res.is_err() {
+                crate::metrics::REDIS_PUBLISH_FAILURES.inc();
+            }
+            res?;
+            std::result::Result::<(), Box<dyn std::error::Error>>::Ok(())
         };
-        match safecall() {
-            | Ok(_) => {},
-            | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
+        Box::pin(fut.into_actor(self).map(|res, act, _| {
+            if let Err(e) = res {
+                act.log_error(e.as_ref());
+            }
+        }))
+    }
+}
+
+/// Handler for messages that are sent from this server towards any client.
+impl Handler<BroadcastServerMessage> for Server {
+    type Result = ResponseActFuture<Self, ()>;
+
+    /// This function will take the message and post it into the broadcast
+    /// redis pub/sub channel of the group which every instance listens to.
+    fn handle(&mut self, msg: BroadcastServerMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        match &msg {
+            | BroadcastServerMessage::All { .. } => {
+                crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerBroadcastMessageEnqueue {},
+                });
+            },
+            | BroadcastServerMessage::Endpoint { endpoint, .. } => {
+                crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+                    data: crate::logger::Event::ServerEndpointBroadcastMessageEnqueue { endpoint },
                 });
             },
         }
+
+        let redis_message: hydrogen_bus::redis::Message = msg.into();
+        let channel = hydrogen_bus::channels::broadcast(&self.config.group_id);
+        let mut redis = self.redis.clone();
+
+        let fut = async move {
+            let res = redis::pipe()
+                .publish(channel, serde_json::to_string(&redis_message)?)
+                .query_async::<_, ()>(&mut redis)
+                .await;
+            if res.is_err() {
+                crate::metrics::REDIS_PUBLISH_FAILURES.inc();
+            }
+            res?;
+            std::result::Result::<(), Box<dyn std::error::Error>>::Ok(())
+        };
+        Box::pin(fut.into_actor(self).map(|res, act, _| {
+            if let Err(e) = res {
+                act.log_error(e.as_ref());
+            }
+        }))
     }
 }
 
 /// Handler for the event in which the server needs to end the connection to any
 /// client.
 impl Handler<ServerDisconnect> for Server {
-    type Result = ();
+    type Result = ResponseActFuture<Self, std::result::Result<(), u16>>;
 
     /// This function will lookup the mapped instance for the given connection
     /// in redis and post a disconnect request for the specified instance
     /// and the given connection id.
     fn handle(&mut self, msg: ServerDisconnect, _ctx: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                data: crate::logger::Event::ServerDisconnect {
-                    connection: &msg.connection.to_string(),
-                    reason: &msg.reason,
-                },
-            });
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ServerDisconnect {
+                connection: &msg.connection,
+                reason: &msg.reason,
+            },
+        });
 
-            let conn = msg.connection.clone();
-            let redis_message: hydrogen_bus::redis::Message = msg.into();
+        let rkey = self.make_reverse_key(&msg.connection);
+        let redis_message: hydrogen_bus::redis::Message = msg.into();
+        let mut redis = self.redis.clone();
 
-            let target_instance = redis::cmd("GET")
-                .arg(&self.make_reverse_key(&conn))
-                .query::<String>(&mut self.redis.get_connection()?)?;
-            redis::pipe()
-                .publish(target_instance, serde_json::to_string(&redis_message)?)
-                .query::<()>(&mut self.redis.get_connection()?)?;
-            Ok(())
+        let fut = async move {
+            match Self::lookup_owner(&mut redis, &rkey).await? {
+                | Some(owner) => {
+                    Self::publish_to_instance(&mut redis, &owner, &redis_message).await?;
+                    Ok(Ok(()))
+                },
+                | None => Ok(Err(404_u16)),
+            }
         };
-        match safecall() {
-            | Ok(_) => {},
-            | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
-                });
+        Box::pin(fut.into_actor(self).map(
+            |res: std::result::Result<_, Box<dyn std::error::Error>>, act, _| match res {
+                | Ok(v) => v,
+                | Err(e) => {
+                    act.log_error(e.as_ref());
+                    Err(500_u16)
+                },
             },
-        }
+        ))
     }
 }
 
@@ -556,57 +1741,55 @@ impl Handler<ClientMessage> for Server {
     type Result = std::result::Result<(), u16>;
 
     /// This function will publish the message towards a message topic in a
-    /// NATS/Jetstream stream.
+    /// NATS/Jetstream stream. The message is handed to the publisher of the
+    /// stream, which publishes the messages in the order they are received.
     fn handle(&mut self, msg: ClientMessage, _ctx: &mut Context<Self>) -> Self::Result {
-        let safecall = || -> Result<(), Box<dyn std::error::Error>> {
-            crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                data: crate::logger::Event::ClientMessage {
-                    connection: &msg.connection.to_string(),
-                },
-            });
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ClientMessage {
+                connection: &msg.connection,
+            },
+        });
+        crate::metrics::observe_message(&msg.endpoint, "in", msg.message.size());
 
-            match &self.nats_js {
-                | Some(v) => {
-                    let stream_name = match &self.config.server.comms {
-                        | CommsMode::UniServerToClient => {
-                            Err(crate::error::ConfigError::new("server comms mode is uni"))
-                        },
-                        | CommsMode::Bidi { stream } => Ok(stream.name.clone()),
-                    }?;
-                    v.publish_with_options(
-                        &format!("hydrogen.{}.core.v1.$client", self.config.group_id),
-                        serde_json::json!(hydrogen_bus::nats::Message {
-                            meta: hydrogen_bus::nats::MessageMeta {
-                                id: Uuid::new_v4().to_string(),
-                                timestamp: chrono::Utc::now().to_rfc3339(),
-                            },
-                            data: hydrogen_bus::nats::ClientMessage {
-                                instance_id: self.instance.clone(),
-                                connection_id: msg.connection,
-                                endpoint: msg.endpoint,
-                                context: msg.context.into(),
-                                message: msg.message,
-                            }
-                        })
-                        .to_string(),
-                        &nats::jetstream::PublishOptions {
-                            expected_stream: Some(stream_name),
-                            ..Default::default()
-                        },
-                    )?;
-                },
-                | None => {},
-            }
-            Ok(())
+        let stream = match self.config.comms_for(&msg.endpoint) {
+            | CommsMode::UniServerToClient => return Ok(()),
+            | CommsMode::Bidi { stream } => stream.clone(),
         };
-        match safecall() {
-            | Ok(_) => Ok(()),
-            | Err(e) => {
-                crate::logger::LogMessage::now(&self.instance.to_string(), crate::logger::Data::Event {
-                    data: crate::logger::Event::Error { err: &e.to_string() },
-                });
-                Err(500_u16)
+        let publisher = match self.publishers.get(&stream.endpoint) {
+            | Some(v) => v,
+            | None => return Ok(()),
+        };
+        let subject = format!("hydrogen.{}.core.v1.$client", self.config.group_id);
+        let cx = hydrogen_telemetry::start(
+            "jetstream.publish",
+            opentelemetry::trace::SpanKind::Producer,
+            &msg.trace,
+            vec![opentelemetry::KeyValue::new(
+                "messaging.destination.name",
+                subject.clone(),
+            )],
+        );
+
+        let (content_type, message) = msg.message.into_bus();
+        let payload = serde_json::json!(hydrogen_bus::nats::Message {
+            meta: hydrogen_bus::nats::MessageMeta {
+                id: Uuid::new_v4().to_string(),
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                traceparent: hydrogen_telemetry::inject(&cx).remove("traceparent"),
             },
-        }
+            data: hydrogen_bus::nats::ClientMessage {
+                instance_id: self.instance.clone(),
+                connection_id: msg.connection,
+                endpoint: msg.endpoint,
+                context: msg.context.into(),
+                content_type,
+                message,
+            }
+        })
+        .to_string();
+        publisher.publish(subject, stream.name, payload, cx).map_err(|e| {
+            self.log_error(&e);
+            500_u16
+        })
     }
 }
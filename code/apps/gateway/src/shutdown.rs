@@ -0,0 +1,89 @@
+use std::{
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    time::Duration,
+};
+
+use actix::Addr;
+use actix_web::{
+    dev::ServerHandle,
+    web::Data,
+};
+
+use crate::{
+    messages::{
+        Drain,
+        PendingSessions,
+    },
+    server::Server,
+};
+
+/// Marks the instance as shutting down, no more connections are accepted
+/// once it is set.
+#[derive(Debug, Default)]
+pub struct Draining(AtomicBool);
+
+impl Draining {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn set(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+}
+
+/// Resolves once the process receives SIGTERM or SIGINT.
+pub async fn signal() -> std::io::Result<()> {
+    let mut sigterm = actix_web::rt::signal::unix::signal(actix_web::rt::signal::unix::SignalKind::terminate())?;
+    let res = match futures::future::select(Box::pin(actix_web::rt::signal::ctrl_c()), Box::pin(sigterm.recv())).await {
+        | futures::future::Either::Left((res, _)) => res,
+        | futures::future::Either::Right(_) => Ok(()),
+    };
+    res
+}
+
+/// Waits for a shutdown signal, then stops accepting connections, closes the
+/// open ones within the drain window and stops the HTTP servers once their
+/// disconnects have been processed (or the grace period is over).
+pub async fn run(
+    instance: String,
+    settings: crate::config::Shutdown,
+    server: Addr<Server>,
+    draining: Data<Draining>,
+    handles: Vec<ServerHandle>,
+) -> std::io::Result<()> {
+    signal().await?;
+    crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
+        data: crate::logger::Event::Shutdown {
+            message: &format!("draining connections within {}s", settings.drain_window_sec),
+        },
+    });
+    draining.set();
+
+    let window = Duration::from_secs(settings.drain_window_sec.into());
+    let deadline = std::time::Instant::now() + window + Duration::from_secs(settings.grace_sec.into());
+    server.do_send(Drain {
+        window,
+        code: settings.close_code,
+        reason: settings.close_reason,
+    });
+    while std::time::Instant::now() < deadline {
+        match server.send(PendingSessions).await {
+            | Ok(0) | Err(_) => break,
+            | Ok(_) => actix_web::rt::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+
+    crate::logger::LogMessage::now(&instance, crate::logger::Data::Event {
+        data: crate::logger::Event::Shutdown {
+            message: "stopping servers",
+        },
+    });
+    for handle in handles {
+        handle.stop(true).await;
+    }
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use std::time::Instant;
+use std::{
+    pin::Pin,
+    task::Poll,
+    time::Instant,
+};
 
 use actix::{
     fut,
@@ -14,6 +18,19 @@ use actix::{
     StreamHandler,
     WrapFuture,
 };
+use actix_codec::Decoder;
+use actix_http::ws::{
+    Codec,
+    Frame,
+    Item,
+};
+use actix_web::{
+    error::PayloadError,
+    web::{
+        Bytes,
+        BytesMut,
+    },
+};
 use actix_web_actors::{
     ws,
     ws::{
@@ -25,20 +42,169 @@ use actix_web_actors::{
 use uuid::Uuid;
 
 use crate::{
+    config::RateLimitViolation,
     messages::{
         ClientMessage,
         Connect,
         Disconnect,
         Heartbeat,
+        Payload,
+        Reply,
         WsMessage,
     },
     server::Server,
 };
 
+/// Error frame sent to clients exceeding their rate limits if configured.
+const RATE_LIMIT_ERROR: &str = r#"{"error":"rate_limit_exceeded"}"#;
+
 pub type WsConnContextMap = std::collections::HashMap<String, serde_json::Value>;
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WsConnContext {
     pub authorizer: std::option::Option<WsConnContextMap>,
+    /// Identity of the user behind the connection, shared by all of its
+    /// connections.
+    #[serde(default)]
+    pub principal: std::option::Option<String>,
+    /// Rooms the connection joins once it is established.
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    /// Subprotocol negotiated with the client during the upgrade.
+    #[serde(default)]
+    pub subprotocol: std::option::Option<String>,
+    /// Trace context of the upgrade, parent of the connect and disconnect
+    /// route calls.
+    #[serde(skip)]
+    pub trace: opentelemetry::Context,
+}
+
+/// Settings that govern the lifecycle of a single connection.
+#[derive(Debug)]
+pub struct WsConnSettings {
+    pub heartbeat_interval: std::time::Duration,
+    pub timeout: std::time::Duration,
+    pub max_in_message_size: usize,
+    pub max_out_message_size: usize,
+    pub limits: std::option::Option<crate::limits::ConnectionLimits>,
+    /// Held for the lifetime of the connection.
+    #[allow(dead_code)]
+    pub admission: std::option::Option<crate::admission::AdmissionSlot>,
+}
+
+/// A fragmented client message that is reassembled from continuation frames.
+enum Fragments {
+    Text(BytesMut),
+    Binary(BytesMut),
+}
+
+impl Fragments {
+    fn buffer(&mut self) -> &mut BytesMut {
+        match self {
+            | Fragments::Text(v) => v,
+            | Fragments::Binary(v) => v,
+        }
+    }
+
+    /// Collects a continuation frame into `fragments` and returns the
+    /// reassembled message once the last frame has been received. Returns the
+    /// close code in case the client violated the protocol or the size limit.
+    fn push(
+        fragments: &mut std::option::Option<Fragments>,
+        item: Item,
+        max_size: usize,
+    ) -> std::result::Result<std::option::Option<Payload>, ws::CloseCode> {
+        let (chunk, last) = match (item, fragments.is_some()) {
+            | (Item::FirstText(v), false) => {
+                *fragments = Some(Fragments::Text(BytesMut::new()));
+                (v, false)
+            },
+            | (Item::FirstBinary(v), false) => {
+                *fragments = Some(Fragments::Binary(BytesMut::new()));
+                (v, false)
+            },
+            | (Item::Continue(v), true) => (v, false),
+            | (Item::Last(v), true) => (v, true),
+            | _ => return Err(ws::CloseCode::Protocol),
+        };
+
+        // fragment buffer is guaranteed to exist at this point
+        let buffer = fragments.as_mut().unwrap().buffer();
+        if buffer.len() + chunk.len() > max_size {
+            *fragments = None;
+            return Err(ws::CloseCode::Size);
+        }
+        buffer.extend_from_slice(&chunk);
+
+        if !last {
+            return Ok(None);
+        }
+        match fragments.take().unwrap() {
+            | Fragments::Text(v) => Ok(Some(Payload::Text(
+                String::from_utf8(v.to_vec()).map_err(|_| ws::CloseCode::Invalid)?,
+            ))),
+            | Fragments::Binary(v) => Ok(Some(Payload::Binary(v.to_vec()))),
+        }
+    }
+
+    /// Checks that a message may be received while `fragments` is being
+    /// reassembled. Only control frames may interleave with the fragments of
+    /// a message, a new text or binary message violates the protocol.
+    fn permits(
+        fragments: &std::option::Option<Fragments>,
+        message: &ws::Message,
+    ) -> std::result::Result<(), ws::CloseCode> {
+        match (fragments, message) {
+            | (Some(_), ws::Message::Text(_) | ws::Message::Binary(_)) => Err(ws::CloseCode::Protocol),
+            | _ => Ok(()),
+        }
+    }
+}
+
+/// Decodes the frames sent by the client. Unlike the stream of the websocket
+/// context, text frames are passed on as they are so the connection can
+/// validate their encoding itself.
+pub struct Frames<S> {
+    stream: S,
+    codec: Codec,
+    buffer: BytesMut,
+    closed: bool,
+}
+
+impl<S> Frames<S> {
+    pub fn new(stream: S, max_size: usize) -> Self {
+        Self {
+            stream,
+            codec: Codec::new().max_size(max_size),
+            buffer: BytesMut::new(),
+            closed: false,
+        }
+    }
+}
+
+impl<S> futures::Stream for Frames<S>
+where S: futures::Stream<Item=Result<Bytes, PayloadError>>+Unpin
+{
+    type Item = Result<Frame, ws::ProtocolError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<std::option::Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.closed {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                | Poll::Ready(Some(Ok(chunk))) => this.buffer.extend_from_slice(&chunk),
+                | Poll::Ready(Some(Err(e))) => {
+                    return Poll::Ready(Some(Err(ws::ProtocolError::Io(std::io::Error::other(e.to_string())))))
+                },
+                | Poll::Ready(None) => this.closed = true,
+                | Poll::Pending => break,
+            }
+        }
+        match this.codec.decode(&mut this.buffer) {
+            | Ok(Some(v)) => Poll::Ready(Some(Ok(v))),
+            | Ok(None) if this.closed => Poll::Ready(None),
+            | Ok(None) => Poll::Pending,
+            | Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
 }
 
 /// Type representing all relevant information about an established websocket
@@ -46,12 +212,14 @@ pub struct WsConnContext {
 pub struct WsConn {
     address: Addr<Server>,
     heartbeat: Instant,
+    instance: String,
     connection: String,
     group: String,
     endpoint: String,
     context: WsConnContext,
-    heartbeat_int: std::time::Duration,
-    timeout: std::time::Duration,
+    settings: WsConnSettings,
+    fragments: std::option::Option<Fragments>,
+    frames: std::option::Option<Frames<actix_web::web::Payload>>,
 }
 
 impl WsConn {
@@ -61,24 +229,32 @@ impl WsConn {
 
     pub fn new(
         connection: String,
+        instance: String,
         group: String,
         endpoint: String,
         server: Addr<Server>,
         context: WsConnContext,
-        heartbeat_int: std::time::Duration,
-        timeout: std::time::Duration,
+        settings: WsConnSettings,
     ) -> WsConn {
         WsConn {
+            instance,
             connection,
             group,
             endpoint,
             address: server,
             heartbeat: Instant::now(),
             context,
-            heartbeat_int,
-            timeout,
+            settings,
+            fragments: None,
+            frames: None,
         }
     }
+
+    /// Sets the frames of the client the connection reads once it is started.
+    pub fn read(mut self, frames: Frames<actix_web::web::Payload>) -> Self {
+        self.frames = Some(frames);
+        self
+    }
 }
 
 impl Actor for WsConn {
@@ -87,7 +263,10 @@ impl Actor for WsConn {
     /// Handles the connection initiation for a client to server connection when
     /// it has already been established.
     fn started(&mut self, ctx: &mut Self::Context) {
-        self.heartbeat(ctx, self.heartbeat_int, self.timeout);
+        if let Some(frames) = self.frames.take() {
+            ctx.add_stream(frames);
+        }
+        self.heartbeat(ctx, self.settings.heartbeat_interval, self.settings.timeout);
 
         let addr = ctx.address();
         self.address
@@ -97,6 +276,13 @@ impl Actor for WsConn {
                 connection: self.connection.clone(),
                 group_id: self.group.clone(),
                 endpoint: self.endpoint.clone(),
+                context: crate::messages::ConnectionContext {
+                    authorizer: self.context.authorizer.clone(),
+                    principal: self.context.principal.clone(),
+                    subprotocol: self.context.subprotocol.clone(),
+                },
+                rooms: self.context.rooms.clone(),
+                trace: self.context.trace.clone(),
             })
             .into_actor(self)
             .then(
@@ -123,6 +309,8 @@ impl Actor for WsConn {
             group_id: self.group.clone(),
             endpoint: self.endpoint.clone(),
             time: chrono::Utc::now().to_rfc3339(),
+            principal: self.context.principal.clone(),
+            trace: self.context.trace.clone(),
         });
         Running::Stop
     }
@@ -136,22 +324,118 @@ impl WsConn {
         interval: std::time::Duration,
         timeout: std::time::Duration,
     ) {
-        let endpoint = self.endpoint.clone();
-        let group = self.group.clone();
         ctx.run_interval(interval, move |act, ctx| {
             if Instant::now().duration_since(act.heartbeat) > timeout {
-                act.address.do_send(Disconnect {
-                    connection: act.connection.clone(),
-                    group_id: group.clone(),
-                    endpoint: endpoint.clone(),
-                    time: chrono::Utc::now().to_rfc3339(),
-                });
+                // the disconnect is issued once when the actor is stopping
                 ctx.stop();
                 return;
             }
             ctx.ping(b".");
         });
     }
+
+    /// Maps a protocol error to the close code the client is answered with
+    /// (RFC 6455, section 7.4.1). Returns `None` if the underlying transport
+    /// failed and no close frame can be delivered anymore.
+    fn close_code(err: &ws::ProtocolError) -> std::option::Option<ws::CloseCode> {
+        match err {
+            | ws::ProtocolError::Overflow => Some(ws::CloseCode::Size),
+            | ws::ProtocolError::Io(_) => None,
+            | _ => Some(ws::CloseCode::Protocol),
+        }
+    }
+
+    /// Converts a frame of the client into the message it carries. Text frames
+    /// that are not valid UTF-8 are answered with `CloseCode::Invalid`.
+    fn message(frame: Frame) -> std::result::Result<ws::Message, ws::CloseCode> {
+        Ok(match frame {
+            | Frame::Text(v) => ws::Message::Text(std::str::from_utf8(&v).map_err(|_| ws::CloseCode::Invalid)?.into()),
+            | Frame::Binary(v) => ws::Message::Binary(v),
+            | Frame::Continuation(v) => ws::Message::Continuation(v),
+            | Frame::Ping(v) => ws::Message::Ping(v),
+            | Frame::Pong(v) => ws::Message::Pong(v),
+            | Frame::Close(v) => ws::Message::Close(v),
+        })
+    }
+
+    /// Logs the protocol violation of the client and closes the connection
+    /// with the given code if it can still be delivered.
+    fn reject(&mut self, err: &str, code: std::option::Option<ws::CloseCode>, ctx: &mut ws::WebsocketContext<Self>) {
+        crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+            data: crate::logger::Event::ProtocolError {
+                connection: &self.connection,
+                err,
+            },
+        });
+        if let Some(code) = code {
+            ctx.close(Some(code.into()));
+        }
+        // stopping the actor runs the regular disconnect flow
+        ctx.stop();
+    }
+
+    /// Collects a continuation frame and forwards the reassembled message to
+    /// the server once the last frame has been received.
+    fn reassemble(
+        &mut self,
+        item: Item,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> std::result::Result<(), ws::CloseCode> {
+        if let Some(message) = Fragments::push(&mut self.fragments, item, self.settings.max_in_message_size)? {
+            self.dispatch(message, ctx);
+        }
+        Ok(())
+    }
+
+    /// Forwards a message received from the client to the server unless it
+    /// exceeds the rate limits of the connection.
+    fn dispatch(&mut self, message: Payload, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(limits) = self.settings.limits.as_mut() {
+            if !limits.acquire(message.size()) {
+                crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+                    data: crate::logger::Event::RateLimitExceeded {
+                        connection: &self.connection,
+                    },
+                });
+                match limits.on_violation() {
+                    | RateLimitViolation::Drop => {},
+                    | RateLimitViolation::Error => ctx.text(RATE_LIMIT_ERROR),
+                    | RateLimitViolation::Close => {
+                        ctx.close(Some(CloseReason {
+                            code: ws::CloseCode::Policy,
+                            description: Some("rate limit exceeded".to_owned()),
+                        }));
+                        ctx.stop();
+                    },
+                }
+                return;
+            }
+        }
+        // every message is traced on its own instead of as part of the
+        // long-lived connection
+        let trace = hydrogen_telemetry::start(
+            "ws.message",
+            opentelemetry::trace::SpanKind::Server,
+            &opentelemetry::Context::new(),
+            vec![
+                opentelemetry::KeyValue::new("hydrogen.connection_id", self.connection.clone()),
+                opentelemetry::KeyValue::new("hydrogen.endpoint", self.endpoint.clone()),
+            ],
+        );
+        self.address.do_send(ClientMessage {
+            connection: self.connection.clone(),
+            group_id: self.group.clone(),
+            endpoint: self.endpoint.clone(),
+            time: chrono::Utc::now().to_rfc3339(),
+            context: crate::messages::ConnectionContext {
+                authorizer: self.context.authorizer.clone(),
+                principal: self.context.principal.clone(),
+                subprotocol: self.context.subprotocol.clone(),
+            },
+            message,
+            trace,
+        })
+    }
 }
 
 /// Main handler for all immediate socket and context related operations on the
@@ -161,11 +445,17 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConn {
     /// websocket connection such as heartbeats, messages and binary data
     /// transfer.
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if let Ok(Err(code)) = msg.as_ref().map(|v| Fragments::permits(&self.fragments, v)) {
+            self.reject("data frame within a fragmented message", Some(code), ctx);
+            return;
+        }
         match msg {
             | Ok(ws::Message::Ping(msg)) => {
                 self.heartbeat = Instant::now();
                 self.address.do_send(Heartbeat {
                     connection: self.connection.clone(),
+                    endpoint: self.endpoint.clone(),
+                    principal: self.context.principal.clone(),
                     time: chrono::Utc::now().to_rfc3339(),
                 });
                 ctx.pong(&msg);
@@ -174,29 +464,37 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConn {
                 self.heartbeat = Instant::now();
                 self.address.do_send(Heartbeat {
                     connection: self.connection.clone(),
+                    endpoint: self.endpoint.clone(),
+                    principal: self.context.principal.clone(),
                     time: chrono::Utc::now().to_rfc3339(),
                 });
             },
-            | Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
+            | Ok(ws::Message::Binary(bin)) => self.dispatch(Payload::Binary(bin.to_vec()), ctx),
             | Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
                 ctx.stop();
             },
-            | Ok(ws::Message::Continuation(_)) => {
-                ctx.stop();
+            | Ok(ws::Message::Continuation(item)) => {
+                if let Err(code) = self.reassemble(item, ctx) {
+                    ctx.close(Some(code.into()));
+                    ctx.stop();
+                }
             },
             | Ok(ws::Message::Nop) => (),
-            | Ok(Text(s)) => self.address.do_send(ClientMessage {
-                connection: self.connection.clone(),
-                group_id: self.group.clone(),
-                endpoint: self.endpoint.clone(),
-                time: chrono::Utc::now().to_rfc3339(),
-                context: crate::messages::ConnectionContext {
-                    authorizer: self.context.authorizer.clone(),
-                },
-                message: s.to_string(),
-            }),
-            | Err(e) => panic!("{}", e),
+            | Ok(Text(s)) => self.dispatch(Payload::Text(s.to_string()), ctx),
+            | Err(e) => self.reject(&e.to_string(), Self::close_code(&e), ctx),
+        }
+    }
+}
+
+/// Handler for the frames decoded from the client, the stream of the
+/// websocket context itself stays empty.
+impl StreamHandler<Result<Frame, ws::ProtocolError>> for WsConn {
+    fn handle(&mut self, frame: Result<Frame, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match frame.map(Self::message) {
+            | Ok(Ok(msg)) => StreamHandler::<Result<ws::Message, ws::ProtocolError>>::handle(self, Ok(msg), ctx),
+            | Ok(Err(code)) => self.reject("invalid utf-8 in text frame", Some(code), ctx),
+            | Err(e) => StreamHandler::<Result<ws::Message, ws::ProtocolError>>::handle(self, Err(e), ctx),
         }
     }
 }
@@ -208,8 +506,30 @@ impl Handler<WsMessage> for WsConn {
     /// Will handle low-level server events for a given connection.
     fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) {
         match msg {
-            | WsMessage::Message { message, .. } => {
-                ctx.text(message);
+            | WsMessage::Message {
+                endpoint,
+                message,
+                reply_to,
+            } => {
+                let reply = if message.size() > self.settings.max_out_message_size {
+                    crate::logger::LogMessage::now(&self.instance, crate::logger::Data::Event {
+                        data: crate::logger::Event::OutMessageTooLarge {
+                            connection: &self.connection,
+                            size: message.size(),
+                        },
+                    });
+                    hydrogen_bus::redis::Reply::TooLarge
+                } else {
+                    crate::metrics::observe_message(&endpoint, "out", message.size());
+                    match message {
+                        | Payload::Text(v) => ctx.text(v),
+                        | Payload::Binary(v) => ctx.binary(v),
+                    }
+                    hydrogen_bus::redis::Reply::Delivered
+                };
+                if let Some(reply_to) = reply_to {
+                    self.address.do_send(Reply { reply_to, reply });
+                }
             },
             | WsMessage::Disconnect(v) => {
                 ctx.close(Some(CloseReason {
@@ -218,6 +538,145 @@ impl Handler<WsMessage> for WsConn {
                 }));
                 ctx.stop();
             },
+            | WsMessage::Close { code, reason } => {
+                ctx.close(Some(CloseReason {
+                    code: code.into(),
+                    description: Some(reason).filter(|v| !v.is_empty()),
+                }));
+                ctx.stop();
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[test]
+    fn text_frames_with_invalid_utf8_are_rejected() {
+        // masked text frames as sent by clients, the zero mask keeps the payload as is
+        let frames = Frames::new(
+            futures::stream::iter(vec![
+                Ok(Bytes::from_static(&[0x81, 0x82, 0, 0, 0, 0, 0xc3, 0x28])),
+                Ok(Bytes::from_static(&[0x81, 0x82, 0, 0, 0, 0, 0xc3, 0xa4])),
+            ]),
+            16,
+        );
+        let messages = futures::executor::block_on(frames.map(|v| WsConn::message(v.unwrap())).collect::<Vec<_>>());
+        assert_eq!(messages, vec![
+            Err(ws::CloseCode::Invalid),
+            Ok(ws::Message::Text("ä".into()))
+        ]);
+    }
+
+    #[test]
+    fn frames_exceeding_the_size_limit_are_rejected() {
+        let mut frames = Frames::new(
+            futures::stream::iter(vec![Ok(Bytes::from_static(&[0x82, 0x84, 0, 0, 0, 0, 1, 2, 3, 4]))]),
+            2,
+        );
+        assert!(matches!(
+            futures::executor::block_on(frames.next()),
+            Some(Err(ws::ProtocolError::Overflow))
+        ));
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_order() {
+        let mut fragments = None;
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::FirstText(Bytes::from_static(b"he")), 16),
+            Ok(None)
+        );
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Continue(Bytes::from_static(b"ll")), 16),
+            Ok(None)
+        );
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Last(Bytes::from_static(b"o")), 16),
+            Ok(Some(Payload::Text("hello".to_owned())))
+        );
+        assert!(fragments.is_none());
+    }
+
+    #[test]
+    fn binary_fragments_are_reassembled() {
+        let mut fragments = None;
+        Fragments::push(&mut fragments, Item::FirstBinary(Bytes::from_static(&[0, 1])), 16).unwrap();
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Last(Bytes::from_static(&[2])), 16),
+            Ok(Some(Payload::Binary(vec![0, 1, 2])))
+        );
+    }
+
+    #[test]
+    fn fragments_out_of_order_are_rejected() {
+        let mut fragments = None;
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Continue(Bytes::from_static(b"a")), 16),
+            Err(ws::CloseCode::Protocol)
+        );
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Last(Bytes::from_static(b"a")), 16),
+            Err(ws::CloseCode::Protocol)
+        );
+
+        Fragments::push(&mut fragments, Item::FirstText(Bytes::from_static(b"a")), 16).unwrap();
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::FirstBinary(Bytes::from_static(b"b")), 16),
+            Err(ws::CloseCode::Protocol)
+        );
+    }
+
+    #[test]
+    fn only_control_frames_interleave_with_fragments() {
+        let mut fragments = None;
+        assert_eq!(Fragments::permits(&fragments, &ws::Message::Text("a".into())), Ok(()));
+        assert_eq!(
+            Fragments::permits(&fragments, &ws::Message::Binary(Bytes::new())),
+            Ok(())
+        );
+
+        Fragments::push(&mut fragments, Item::FirstText(Bytes::from_static(b"a")), 16).unwrap();
+        assert_eq!(Fragments::permits(&fragments, &ws::Message::Ping(Bytes::new())), Ok(()));
+        assert_eq!(Fragments::permits(&fragments, &ws::Message::Pong(Bytes::new())), Ok(()));
+        assert_eq!(Fragments::permits(&fragments, &ws::Message::Close(None)), Ok(()));
+        assert_eq!(
+            Fragments::permits(&fragments, &ws::Message::Continuation(Item::Last(Bytes::new()))),
+            Ok(())
+        );
+        assert_eq!(
+            Fragments::permits(&fragments, &ws::Message::Text("b".into())),
+            Err(ws::CloseCode::Protocol)
+        );
+        assert_eq!(
+            Fragments::permits(&fragments, &ws::Message::Binary(Bytes::new())),
+            Err(ws::CloseCode::Protocol)
+        );
+    }
+
+    #[test]
+    fn fragments_exceeding_the_size_limit_are_rejected() {
+        let mut fragments = None;
+        Fragments::push(&mut fragments, Item::FirstText(Bytes::from_static(b"1234")), 6).unwrap();
+        Fragments::push(&mut fragments, Item::Continue(Bytes::from_static(b"56")), 6).unwrap();
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Last(Bytes::from_static(b"7")), 6),
+            Err(ws::CloseCode::Size)
+        );
+        assert!(fragments.is_none());
+    }
+
+    #[test]
+    fn fragmented_text_with_invalid_utf8_is_rejected() {
+        let mut fragments = None;
+        Fragments::push(&mut fragments, Item::FirstText(Bytes::from_static(&[0xe2, 0x82])), 16).unwrap();
+        assert_eq!(
+            Fragments::push(&mut fragments, Item::Last(Bytes::from_static(&[0x28])), 16),
+            Err(ws::CloseCode::Invalid)
+        );
+    }
+}
@@ -0,0 +1,732 @@
+//! Integration tests running two gateway instances of the same group against a
+//! local redis. They are ignored by default, run them with
+//! `cargo test -- --ignored` and point `HYDROGEN_TEST_REDIS` to the redis
+//! instance if it is not reachable at `redis://127.0.0.1:6379`.
+
+use std::{
+    io::Write,
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    time::Duration,
+};
+
+use actix_http::ws;
+use futures::{
+    SinkExt,
+    StreamExt,
+};
+
+type WsClient = actix_codec::Framed<awc::BoxedSocket, ws::Codec>;
+
+fn redis_endpoint() -> String {
+    std::env::var("HYDROGEN_TEST_REDIS").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_owned())
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// A gateway process that is killed when dropped.
+struct Gateway {
+    process: Child,
+    address: String,
+}
+
+impl Gateway {
+    async fn start(group: &str) -> Self {
+        Self::start_with_routes(group, "").await
+    }
+
+    /// Starts a gateway with additional (indented) YAML below `routes`.
+    async fn start_with_routes(group: &str, routes: &str) -> Self {
+        Self::start_with_timings(group, 10, 40, routes).await
+    }
+
+    /// Starts a gateway with the given heartbeat interval and mapping TTL.
+    async fn start_with_timings(group: &str, heartbeat_interval_sec: u64, mapping_ttl_sec: u64, routes: &str) -> Self {
+        let port = free_port();
+        let config = format!(
+            r#"
+version: 0.1.0
+group_id: "{group}"
+server:
+  address: "127.0.0.1:{port}"
+  heartbeat_interval_sec: {heartbeat_interval_sec}
+  connection_timeout_sec: 31
+  ack_timeout_sec: 5
+  max_out_message_size: 262144
+  max_in_message_size: 262144
+  comms: uni_server_to_client
+redis:
+  endpoint: "{redis}"
+  mapping_ttl_sec: {mapping_ttl_sec}
+routes:
+  endpoints:
+    - path: "/"
+{routes}
+"#,
+            group = group,
+            port = port,
+            heartbeat_interval_sec = heartbeat_interval_sec,
+            mapping_ttl_sec = mapping_ttl_sec,
+            redis = redis_endpoint(),
+            routes = routes
+        );
+        let path = std::env::temp_dir().join(format!("hydrogen-gateway-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(config.as_bytes())
+            .unwrap();
+
+        let gateway = Self {
+            process: Command::new(env!("CARGO_BIN_EXE_hydrogen-gateway"))
+                .args(["serve", "-c", path.to_str().unwrap()])
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap(),
+            address: format!("127.0.0.1:{}", port),
+        };
+        for _ in 0..100 {
+            if let Ok(resp) = awc::Client::default().get(gateway.url("/health")).send().await {
+                if resp.status().is_success() {
+                    return gateway;
+                }
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("gateway did not become healthy");
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    /// Connects a new client and returns it together with its connection id.
+    async fn connect(&self, group: &str) -> (WsClient, String) {
+        let known = connections(group);
+        let (_, client) = awc::Client::default()
+            .ws(format!("ws://{}/ws/", self.address))
+            .connect()
+            .await
+            .unwrap();
+        for _ in 0..100 {
+            if let Some(id) = connections(group).into_iter().find(|v| !known.contains(v)) {
+                return (client, id);
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("connection has not been registered");
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Returns the ids of all connections of the group that are registered in
+/// redis.
+fn connections(group: &str) -> Vec<String> {
+    let prefix = format!("hydrogen:group:{}:c2i:", group);
+    let mut conn = redis::Client::open(redis_endpoint()).unwrap().get_connection().unwrap();
+    redis::cmd("KEYS")
+        .arg(format!("{}*", prefix))
+        .query::<Vec<String>>(&mut conn)
+        .unwrap()
+        .into_iter()
+        .map(|v| v.trim_start_matches(&prefix).to_owned())
+        .collect()
+}
+
+/// Waits for the next frame that is not a heartbeat.
+async fn next_frame(client: &mut WsClient) -> ws::Frame {
+    loop {
+        let frame = actix_web::rt::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("no frame received")
+            .unwrap()
+            .unwrap();
+        match frame {
+            | ws::Frame::Ping(v) => client.send(ws::Message::Pong(v)).await.unwrap(),
+            | ws::Frame::Pong(_) => {},
+            | v => return v,
+        }
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn send_reaches_connection_on_other_instance() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let b = Gateway::start(&group).await;
+    let (mut client, id) = b.connect(&group).await;
+
+    let resp = awc::Client::default()
+        .post(a.url(&format!("/connections/{}/_send?ack=true", id)))
+        .send_body("hello")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut client).await, ws::Frame::Text("hello".into()));
+
+    let resp = awc::Client::default()
+        .post(a.url(&format!("/connections/{}/_send", id)))
+        .content_type("application/octet-stream")
+        .send_body(vec![0_u8, 1, 2])
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        next_frame(&mut client).await,
+        ws::Frame::Binary(vec![0_u8, 1, 2].into())
+    );
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn send_to_unknown_connection_is_rejected() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+
+    let resp = awc::Client::default()
+        .post(a.url("/connections/unknown/_send"))
+        .send_body("hello")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let resp = awc::Client::default()
+        .post(a.url("/connections/unknown/_disconnect"))
+        .send_body("bye")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn disconnect_reaches_connection_on_other_instance() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let b = Gateway::start(&group).await;
+    let (mut client, id) = b.connect(&group).await;
+
+    let resp = awc::Client::default()
+        .post(a.url(&format!("/connections/{}/_disconnect", id)))
+        .send_body("bye")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        next_frame(&mut client).await,
+        ws::Frame::Close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("bye".to_owned()),
+        }))
+    );
+
+    for _ in 0..100 {
+        if !connections(&group).contains(&id) {
+            return;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("connection mapping has not been removed");
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn broadcast_reaches_connections_on_all_instances() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let b = Gateway::start(&group).await;
+    let (mut client_a, _) = a.connect(&group).await;
+    let (mut client_b, _) = b.connect(&group).await;
+
+    let resp = awc::Client::default()
+        .post(a.url("/connections/_broadcast"))
+        .send_body("everyone")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut client_a).await, ws::Frame::Text("everyone".into()));
+    assert_eq!(next_frame(&mut client_b).await, ws::Frame::Text("everyone".into()));
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn connections_are_listed_across_instances() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let b = Gateway::start(&group).await;
+    let (_client_a, id_a) = a.connect(&group).await;
+    let (_client_b, id_b) = b.connect(&group).await;
+
+    let mut resp = awc::Client::default()
+        .get(a.url(&format!("/connections/{}", id_b)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let info = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(info["connection_id"], id_b.as_str());
+    assert_eq!(info["endpoint"], "/");
+
+    let resp = awc::Client::default()
+        .get(a.url("/connections/unknown"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let mut found = Vec::new();
+    let mut cursor = Some(0_u64);
+    while let Some(c) = cursor {
+        let mut resp = awc::Client::default()
+            .get(b.url(&format!("/connections?endpoint=/&cursor={}&count=10", c)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        let page = resp.json::<serde_json::Value>().await.unwrap();
+        for info in page["connections"].as_array().unwrap() {
+            found.push(info["connection_id"].as_str().unwrap().to_owned());
+        }
+        cursor = page["cursor"].as_u64();
+    }
+    found.sort();
+    let mut expected = vec![id_a, id_b];
+    expected.sort();
+    assert_eq!(found, expected);
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn malformed_connections_are_skipped() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let (_client, id) = a.connect(&group).await;
+
+    let mut conn = redis::Client::open(redis_endpoint()).unwrap().get_connection().unwrap();
+    redis::cmd("HSET")
+        .arg(format!("hydrogen:group:{}:i2c:gone:malformed", group))
+        .arg("last_heartbeat")
+        .arg("0")
+        .query::<()>(&mut conn)
+        .unwrap();
+    redis::cmd("SET")
+        .arg(format!("hydrogen:group:{}:c2i:malformed", group))
+        .arg("gone")
+        .query::<()>(&mut conn)
+        .unwrap();
+
+    let resp = awc::Client::default()
+        .get(a.url("/connections/malformed"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let mut found = Vec::new();
+    let mut cursor = Some(0_u64);
+    while let Some(c) = cursor {
+        let mut resp = awc::Client::default()
+            .get(a.url(&format!("/connections?cursor={}&count=10", c)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        let page = resp.json::<serde_json::Value>().await.unwrap();
+        for info in page["connections"].as_array().unwrap() {
+            found.push(info["connection_id"].as_str().unwrap().to_owned());
+        }
+        cursor = page["cursor"].as_u64();
+    }
+    assert_eq!(found, vec![id]);
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn room_message_reaches_members_on_all_instances() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let b = Gateway::start(&group).await;
+    let (mut member_a, id_a) = a.connect(&group).await;
+    let (mut member_b, id_b) = b.connect(&group).await;
+    let (mut other, _) = b.connect(&group).await;
+
+    for id in [&id_a, &id_b] {
+        let resp = awc::Client::default()
+            .put(a.url(&format!("/rooms/chat/connections/{}", id)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+    let resp = awc::Client::default()
+        .put(a.url("/rooms/chat/connections/unknown"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let resp = awc::Client::default()
+        .post(b.url("/rooms/chat/_send"))
+        .send_body("members")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut member_a).await, ws::Frame::Text("members".into()));
+    assert_eq!(next_frame(&mut member_b).await, ws::Frame::Text("members".into()));
+
+    let resp = awc::Client::default()
+        .delete(a.url(&format!("/rooms/chat/connections/{}", id_b)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let mut resp = awc::Client::default()
+        .get(a.url("/rooms/chat/connections"))
+        .send()
+        .await
+        .unwrap();
+    let members = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(members["connections"], serde_json::json!([id_a]));
+
+    // the connection that never joined must not have received anything
+    assert!(
+        actix_web::rt::time::timeout(Duration::from_millis(500), next_frame(&mut other))
+            .await
+            .is_err()
+    );
+}
+
+/// Starts an authorizer that assigns every connection to the given principal
+/// and returns the YAML configuring it as authorizer route.
+fn start_authorizer(principal: &'static str) -> String {
+    let port = free_port();
+    let server = actix_web::HttpServer::new(move || {
+        actix_web::App::new().default_service(actix_web::web::to(move || async move {
+            actix_web::HttpResponse::Ok().json(serde_json::json!({ "principal": principal }))
+        }))
+    })
+    .bind(("127.0.0.1", port))
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+    format!(
+        "  authorizer:\n    endpoint: \"http://127.0.0.1:{}\"\n    headers: {{}}",
+        port
+    )
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn principal_is_reached_on_all_instances() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let authorizer = start_authorizer("alice");
+    let a = Gateway::start_with_routes(&group, &authorizer).await;
+    let b = Gateway::start_with_routes(&group, &authorizer).await;
+    let (mut device_a, _) = a.connect(&group).await;
+    let (mut device_b, _) = b.connect(&group).await;
+
+    let resp = awc::Client::default()
+        .post(a.url("/principals/alice/_send"))
+        .send_body("hello alice")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut device_a).await, ws::Frame::Text("hello alice".into()));
+    assert_eq!(next_frame(&mut device_b).await, ws::Frame::Text("hello alice".into()));
+
+    let resp = awc::Client::default()
+        .post(a.url("/principals/bob/_send"))
+        .send_body("hello bob")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let resp = awc::Client::default()
+        .post(b.url("/principals/alice/_disconnect"))
+        .send_body("bye")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    for device in [&mut device_a, &mut device_b] {
+        assert_eq!(
+            next_frame(device).await,
+            ws::Frame::Close(Some(ws::CloseReason {
+                code: ws::CloseCode::Policy,
+                description: Some("bye".to_owned()),
+            }))
+        );
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn endpoint_overrides_outbound_message_size() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start_with_routes(&group, "      max_out_message_size: 5").await;
+    let b = Gateway::start(&group).await;
+    let (mut client, id) = a.connect(&group).await;
+
+    let resp = awc::Client::default()
+        .post(b.url(&format!("/connections/{}/_send?ack=true", id)))
+        .send_body("hello")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut client).await, ws::Frame::Text("hello".into()));
+
+    let resp = awc::Client::default()
+        .post(b.url(&format!("/connections/{}/_send?ack=true", id)))
+        .send_body("hello world")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 413);
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn connection_exceeding_rate_limit_is_closed() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start_with_routes(
+        &group,
+        "      rate_limit:\n        connection:\n          messages:\n            rate_per_sec: 1\n        \
+         on_violation: close",
+    )
+    .await;
+    let (mut client, _) = a.connect(&group).await;
+
+    client.send(ws::Message::Text("first".into())).await.unwrap();
+    client.send(ws::Message::Text("second".into())).await.unwrap();
+    assert_eq!(
+        next_frame(&mut client).await,
+        ws::Frame::Close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("rate limit exceeded".to_owned()),
+        }))
+    );
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn endpoint_connection_limit_applies_across_instances() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let admission = "      admission:\n        max_endpoint_connections: 1\n        retry_after_sec: 5";
+    let a = Gateway::start_with_routes(&group, admission).await;
+    let b = Gateway::start_with_routes(&group, admission).await;
+    let (_client, _) = a.connect(&group).await;
+
+    match awc::Client::default()
+        .ws(format!("ws://{}/ws/", b.address))
+        .connect()
+        .await
+    {
+        | Err(awc::error::WsClientError::InvalidResponseStatus(status)) => assert_eq!(status.as_u16(), 503),
+        | _ => panic!("connection has been admitted"),
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn admission_outlives_the_mapping_ttl() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let admission = "      admission:\n        max_endpoint_connections: 1\n        retry_after_sec: 5";
+    let a = Gateway::start_with_timings(&group, 1, 2, admission).await;
+    let (_client, _) = a.connect(&group).await;
+
+    // heartbeats keep the count alive well past its initial expiry
+    actix_web::rt::time::sleep(Duration::from_secs(5)).await;
+    match awc::Client::default()
+        .ws(format!("ws://{}/ws/", a.address))
+        .connect()
+        .await
+    {
+        | Err(awc::error::WsClientError::InvalidResponseStatus(status)) => assert_eq!(status.as_u16(), 503),
+        | _ => panic!("connection has been admitted"),
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn connections_are_drained_on_sigterm() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let mut a = Gateway::start(&group).await;
+    let (mut client, id) = a.connect(&group).await;
+
+    Command::new("kill")
+        .args(["-TERM", &a.process.id().to_string()])
+        .status()
+        .unwrap();
+    assert_eq!(
+        next_frame(&mut client).await,
+        ws::Frame::Close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: None,
+        }))
+    );
+
+    assert!(a.process.wait().unwrap().success());
+    assert!(!connections(&group).contains(&id));
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn connections_are_restored_after_redis_subscription_failure() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let b = Gateway::start(&group).await;
+    let (mut client, id) = b.connect(&group).await;
+
+    // simulates redis losing the connection state and the subscription of b
+    let mut conn = redis::Client::open(redis_endpoint()).unwrap().get_connection().unwrap();
+    let keys = redis::cmd("KEYS")
+        .arg(format!("hydrogen:group:{}:*", group))
+        .query::<Vec<String>>(&mut conn)
+        .unwrap();
+    redis::cmd("DEL").arg(keys).query::<()>(&mut conn).unwrap();
+    let clients = redis::cmd("CLIENT").arg("LIST").query::<String>(&mut conn).unwrap();
+    for client in clients
+        .lines()
+        .filter(|v| v.contains(&format!("name=hydrogen:group:{}:", group)))
+    {
+        let client_id = client.split(' ').find_map(|v| v.strip_prefix("id=")).unwrap();
+        redis::cmd("CLIENT")
+            .arg("KILL")
+            .arg("ID")
+            .arg(client_id)
+            .query::<()>(&mut conn)
+            .unwrap();
+    }
+
+    for _ in 0..100 {
+        if connections(&group).contains(&id) {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    let resp = awc::Client::default()
+        .post(a.url(&format!("/connections/{}/_send?ack=true", id)))
+        .send_body("hello")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut client).await, ws::Frame::Text("hello".into()));
+
+    let mut resp = awc::Client::default().get(b.url("/health")).send().await.unwrap();
+    assert_eq!(resp.body().await.unwrap(), r#"{"status":"up"}"#);
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn metrics_report_connections_and_messages() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+    let (mut client, id) = a.connect(&group).await;
+
+    let resp = awc::Client::default()
+        .post(a.url(&format!("/connections/{}/_send?ack=true", id)))
+        .send_body("hello")
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(next_frame(&mut client).await, ws::Frame::Text("hello".into()));
+
+    let mut resp = awc::Client::default().get(a.url("/metrics")).send().await.unwrap();
+    let body = String::from_utf8(resp.body().await.unwrap().to_vec()).unwrap();
+    assert!(body.contains(r#"hydrogen_gateway_connections{endpoint="/"} 1"#));
+    assert!(body.contains(r#"hydrogen_gateway_upgrades_total{endpoint="/",result="accepted"} 1"#));
+    assert!(body.contains(r#"hydrogen_gateway_messages_total{direction="out",endpoint="/"} 1"#));
+    assert!(body.contains(r#"hydrogen_gateway_message_bytes_total{direction="out",endpoint="/"} 5"#));
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn trace_context_is_propagated_to_the_authorizer() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+    let port = free_port();
+    let server = {
+        let received = received.clone();
+        actix_web::HttpServer::new(move || {
+            let received = received.clone();
+            actix_web::App::new().default_service(actix_web::web::to(move |req: actix_web::HttpRequest| {
+                let received = received.clone();
+                async move {
+                    if let Some(v) = req.headers().get("traceparent") {
+                        received.lock().unwrap().push(v.to_str().unwrap().to_owned());
+                    }
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({}))
+                }
+            }))
+        })
+        .bind(("127.0.0.1", port))
+        .unwrap()
+        .run()
+    };
+    actix_web::rt::spawn(server);
+    let authorizer = format!(
+        "  authorizer:\n    endpoint: \"http://127.0.0.1:{}\"\n    headers: {{}}",
+        port
+    );
+    let a = Gateway::start_with_routes(&group, &authorizer).await;
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let (_, _client) = awc::Client::default()
+        .ws(format!("ws://{}/ws/", a.address))
+        .header("traceparent", format!("00-{}-00f067aa0ba902b7-01", trace_id))
+        .connect()
+        .await
+        .unwrap();
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].starts_with(&format!("00-{}-", trace_id)));
+    assert!(!received[0].contains("00f067aa0ba902b7"));
+}
+
+#[actix_web::test]
+#[ignore = "requires a local redis"]
+async fn probes_report_dependency_status() {
+    let group = uuid::Uuid::new_v4().to_string();
+    let a = Gateway::start(&group).await;
+
+    let mut resp = awc::Client::default().get(a.url("/health/live")).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.json::<serde_json::Value>().await.unwrap(),
+        serde_json::json!({
+            "status": "up",
+            "checks": {
+                "redis_subscriber": { "status": "up" },
+                "server": { "status": "up" },
+            },
+        })
+    );
+
+    let mut resp = awc::Client::default().get(a.url("/health/ready")).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.json::<serde_json::Value>().await.unwrap(),
+        serde_json::json!({
+            "status": "up",
+            "draining": false,
+            "checks": {
+                "redis": { "status": "up" },
+                "redis_subscription": { "status": "up" },
+            },
+        })
+    );
+}
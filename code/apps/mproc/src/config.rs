@@ -5,6 +5,59 @@ pub struct Config {
     pub group_id: String,
     pub engine_mode: EngineMode,
     pub stream: Stream,
+    pub server: std::option::Option<Server>,
+    pub tracing: std::option::Option<hydrogen_telemetry::Tracing>,
+    pub logging: std::option::Option<Logging>,
+}
+
+/// Filtering, formatting and redaction of the log output.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Logging {
+    /// Default level and per event overrides, such as
+    /// `info,message=debug`. The `HYDROGEN_LOG` environment variable takes
+    /// precedence.
+    #[serde(default)]
+    pub filter: std::option::Option<String>,
+    #[serde(default)]
+    pub format: hydrogen_logger::Format,
+    /// Ratio of the messages of the given events that are logged.
+    #[serde(default)]
+    pub sampling: std::collections::HashMap<String, f64>,
+    #[serde(default)]
+    pub redact: Redaction,
+}
+
+/// Parts of the client messages that are kept out of the log output.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct Redaction {
+    pub body: bool,
+    pub authorizer_context: bool,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            body: true,
+            authorizer_context: true,
+        }
+    }
+}
+
+/// The listener serving the health, readiness and metrics endpoints.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Server {
+    pub address: String,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0:8080".to_owned(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -40,5 +93,13 @@ pub struct DestinationRoute {
 #[serde(rename_all = "snake_case")]
 pub struct RegexRule {
     pub regex: String,
+    /// Restricts the rule to messages of connections that negotiated this
+    /// subprotocol.
+    #[serde(default)]
+    pub subprotocol: std::option::Option<String>,
+    /// Matches every binary message. The regular expression only applies to
+    /// text messages, binary messages are skipped by rules without it.
+    #[serde(default)]
+    pub binary: bool,
     pub route: DestinationRoute,
 }
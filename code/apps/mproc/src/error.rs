@@ -3,3 +3,4 @@ hydrogen_error::make_error!(MissingArgumentError);
 hydrogen_error::make_error!(RulesEngineRouteError);
 hydrogen_error::make_error!(ForwardRouteError);
 hydrogen_error::make_error!(InvalidRegexError);
+hydrogen_error::make_error!(ConfigError);
@@ -0,0 +1,48 @@
+use actix_web::{
+    get,
+    web::Data,
+    Error,
+    HttpResponse,
+};
+
+#[derive(serde::Serialize)]
+struct Health {
+    status: String,
+}
+
+/// Whether the consumer of this instance is attached to the stream.
+#[derive(Debug, Default)]
+pub struct Ready(std::sync::atomic::AtomicBool);
+
+impl Ready {
+    pub fn set(&self, ready: bool) {
+        self.0.store(ready, std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+/// Healthcheck endpoint returning static data.
+#[get("/health")]
+pub async fn handler() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(Health {
+        status: "up".to_owned(),
+    }))
+}
+
+/// Readiness endpoint, responds with `code 503` until the consumer is
+/// attached to the stream.
+#[get("/ready")]
+pub async fn readiness(ready: Data<Ready>) -> Result<HttpResponse, Error> {
+    if ready.is_set() {
+        Ok(HttpResponse::Ok().json(Health {
+            status: "ready".to_owned(),
+        }))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(Health {
+            status: "not_ready".to_owned(),
+        }))
+    }
+}
@@ -0,0 +1,13 @@
+use actix_web::{
+    get,
+    Error,
+    HttpResponse,
+};
+
+/// Metrics endpoint returning the metrics of this instance in the prometheus
+/// text format.
+#[get("/metrics")]
+pub async fn handler() -> Result<HttpResponse, Error> {
+    let body = crate::metrics::encode().map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().content_type(prometheus::TEXT_FORMAT).body(body))
+}
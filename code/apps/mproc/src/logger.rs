@@ -1,19 +1,56 @@
+use std::sync::{
+    Arc,
+    RwLock,
+};
+
+use hydrogen_logger::{
+    Format,
+    Level,
+};
+
+static REDACTION: RwLock<std::option::Option<Arc<crate::config::Redaction>>> = RwLock::new(None);
+
+/// Applies the logging configuration, messages logged before use the
+/// defaults.
+pub fn init(
+    logging: std::option::Option<&crate::config::Logging>,
+) -> std::result::Result<(), hydrogen_logger::ConfigError> {
+    let logging = logging.cloned().unwrap_or_default();
+    hydrogen_logger::init("logging", logging.filter.as_deref(), logging.format, logging.sampling)?;
+    *REDACTION.write().unwrap() = Some(Arc::new(logging.redact));
+    Ok(())
+}
+
+fn redaction() -> Arc<crate::config::Redaction> {
+    REDACTION.read().unwrap().clone().unwrap_or_default()
+}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct LogMessage<'a> {
     time: String,
+    level: Level,
     mproc_instance: &'a str,
     data: Data<'a>,
 }
 
 impl<'a> LogMessage<'a> {
     pub fn now(mproc_instance: &'a str, data: Data<'a>) -> () {
-        Self {
+        let settings = hydrogen_logger::settings();
+        let (name, level) = data.meta();
+        if !settings.enabled(name, level) {
+            return;
+        }
+        let msg = Self {
             time: chrono::Utc::now().to_rfc3339(),
+            level,
             mproc_instance,
             data,
+        };
+        match settings.format() {
+            | Format::Json => msg.log(),
+            | Format::Text => println!("{}", msg.text(name)),
         }
-        .log()
     }
 
     pub fn log(&self) -> () {
@@ -22,6 +59,13 @@ impl<'a> LogMessage<'a> {
             | Err(e) => println!("{}", e),
         }
     }
+
+    /// Renders the message as `time LEVEL instance event key=value...`.
+    fn text(&self, name: &str) -> String {
+        match &self.data {
+            | Data::Event { data } => hydrogen_logger::text(&self.time, self.level, self.mproc_instance, name, data),
+        }
+    }
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -30,15 +74,76 @@ pub enum Data<'a> {
     Event { data: Event<'a> },
 }
 
+impl Data<'_> {
+    /// The name filters and sampling refer to and the level of the message.
+    fn meta(&self) -> (&'static str, Level) {
+        match self {
+            | Data::Event { data } => data.meta(),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Event<'a> {
-    Error { message: &'a str },
-    Startup { message: &'a str },
+    Startup {
+        message: &'a str,
+    },
+
+    RulesEngineRouteResponse {
+        connection: &'a str,
+        response: u16,
+    },
+    DestinationRouteResponse {
+        connection: &'a str,
+        response: u16,
+    },
+
+    Message {
+        connection: &'a str,
+    },
+    MessageFailed {
+        connection: &'a str,
+        message_id: &'a str,
+        err: &'a str,
+        body: Sensitive<'a, str>,
+        authorizer_context: Sensitive<'a, std::option::Option<hydrogen_bus::nats::MessageContextMap>>,
+    },
+    DroppedMessageNoMatch {
+        connection: &'a str,
+    },
+}
 
-    RulesEngineRouteResponse { connection: &'a str, response: u16 },
-    DestinationRouteResponse { connection: &'a str, response: u16 },
+impl Event<'_> {
+    fn meta(&self) -> (&'static str, Level) {
+        match self {
+            | Event::Startup { .. } => ("startup", Level::Info),
 
-    Message { connection: &'a str },
-    DroppedMessageNoMatch { connection: &'a str },
+            | Event::RulesEngineRouteResponse { .. } => ("rules_engine_route_response", Level::Debug),
+            | Event::DestinationRouteResponse { .. } => ("destination_route_response", Level::Debug),
+
+            | Event::Message { .. } => ("message", Level::Debug),
+            | Event::MessageFailed { .. } => ("message_failed", Level::Error),
+            | Event::DroppedMessageNoMatch { .. } => ("dropped_message_no_match", Level::Warn),
+        }
+    }
+}
+
+/// A part of a client message that is written as `[redacted]` unless the
+/// redaction policy allows it.
+#[derive(Debug)]
+pub enum Sensitive<'a, T: ?Sized> {
+    Body(&'a T),
+    AuthorizerContext(&'a T),
+}
+
+impl<T: ?Sized+serde::Serialize> serde::Serialize for Sensitive<'_, T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let redact = redaction();
+        match self {
+            | Sensitive::Body(v) if !redact.body => v.serialize(serializer),
+            | Sensitive::AuthorizerContext(v) if !redact.authorizer_context => v.serialize(serializer),
+            | _ => serializer.serialize_str("[redacted]"),
+        }
+    }
 }
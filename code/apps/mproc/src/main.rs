@@ -2,12 +2,24 @@ mod args;
 mod config;
 mod error;
 mod logger;
+mod metrics;
 mod routes;
+mod handlers {
+    pub mod health;
+    pub mod metrics;
+}
 
 use std::error::Error;
 
+use actix_web::{
+    web::Data,
+    App,
+    HttpServer,
+};
 use futures::StreamExt;
 
+use crate::handlers::health::Ready;
+
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn Error>> {
     logger::LogMessage::now("-", logger::Data::Event {
@@ -17,15 +29,56 @@ async fn main() -> std::result::Result<(), Box<dyn Error>> {
     let args = args::ClapArgumentLoader::load()?;
     match args.command {
         | args::Command::Work { config } => {
-            endless_nats_consumer(&instance.to_string(), &config).await?;
+            logger::init(config.logging.as_ref())?;
+            hydrogen_telemetry::init(
+                "hydrogen-mproc",
+                &instance.to_string(),
+                &config.group_id,
+                config.tracing.as_ref(),
+            )?;
+            let ready = Data::new(Ready::default());
+            let server = serve(
+                &instance.to_string(),
+                config.server.clone().unwrap_or_default(),
+                ready.clone(),
+            )?;
+            tokio::spawn(server);
+            endless_nats_consumer(&instance.to_string(), &config, &ready).await?;
             Ok(())
         },
     }
 }
 
+/// Starts the HTTP server for the health, readiness and metrics endpoints.
+/// The process signals are left to the consumer.
+fn serve(
+    instance: &str,
+    server: crate::config::Server,
+    ready: Data<Ready>,
+) -> std::result::Result<actix_web::dev::Server, Box<dyn Error>> {
+    logger::LogMessage::now(instance, logger::Data::Event {
+        data: logger::Event::Startup {
+            message: &format!("instance will bind @ {}", &server.address),
+        },
+    });
+    crate::metrics::init();
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(ready.clone())
+            .service(crate::handlers::health::handler)
+            .service(crate::handlers::health::readiness)
+            .service(crate::handlers::metrics::handler)
+    })
+    .disable_signals()
+    .workers(1)
+    .bind(&server.address)?
+    .run())
+}
+
 async fn endless_nats_consumer(
     instance: &str,
     config: &crate::config::Config,
+    ready: &Ready,
 ) -> std::result::Result<(), Box<dyn Error>> {
     let nc = async_nats::connect(&config.stream.endpoint).await?;
     let nc2 = async_nats::jetstream::new(nc);
@@ -58,42 +111,98 @@ async fn endless_nats_consumer(
         .unwrap();
 
     let mut messages = consumer.stream().unwrap();
+    ready.set(true);
     while let Some(Ok(message)) = messages.next().await {
+        crate::metrics::CONSUMED.inc();
+        if delivery_count(&message).is_some_and(|v| v > 1) {
+            crate::metrics::REDELIVERED.inc();
+        }
         let msg_str = String::from_utf8(message.payload.to_vec())?;
         let msg_typed: hydrogen_bus::nats::Message<hydrogen_bus::nats::ClientMessage> = serde_json::from_str(&msg_str)?;
-        match handle_nats_message(instance, config, &msg_typed) {
+        let cx = hydrogen_telemetry::start(
+            "mproc.message",
+            opentelemetry::trace::SpanKind::Consumer,
+            &hydrogen_telemetry::extract(
+                msg_typed
+                    .meta
+                    .traceparent
+                    .as_deref()
+                    .map(|v| ("traceparent", v))
+                    .into_iter(),
+            ),
+            vec![
+                opentelemetry::KeyValue::new("hydrogen.connection_id", msg_typed.data.connection_id.clone()),
+                opentelemetry::KeyValue::new("hydrogen.endpoint", msg_typed.data.endpoint.clone()),
+            ],
+        );
+        match handle_nats_message(instance, config, &msg_typed, &cx) {
             | Ok(..) => {
                 message.ack().await.unwrap();
+                crate::metrics::ACKED.inc();
+            },
+            | Err(e) => {
+                crate::metrics::FAILED.inc();
+                hydrogen_telemetry::record_error(&cx, e.as_ref());
+                crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
+                    data: crate::logger::Event::MessageFailed {
+                        connection: &msg_typed.data.connection_id,
+                        message_id: &msg_typed.meta.id,
+                        err: &e.to_string(),
+                        body: crate::logger::Sensitive::Body(&msg_typed.data.message),
+                        authorizer_context: crate::logger::Sensitive::AuthorizerContext(
+                            &msg_typed.data.context.authorizer,
+                        ),
+                    },
+                })
             },
-            | Err(e) => crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
-                data: crate::logger::Event::Error {
-                    message: &format!("error on message: {:?}, details: {}", msg_typed, e.to_string()),
-                },
-            }),
         }
     }
+    ready.set(false);
     Ok(())
 }
 
+/// Reads how often a message has been delivered from its JetStream ack
+/// subject, `$JS.ACK.<stream>.<consumer>.<delivered>...` or
+/// `$JS.ACK.<domain>.<account>.<stream>.<consumer>.<delivered>...`.
+fn delivery_count(message: &async_nats::jetstream::Message) -> std::option::Option<u64> {
+    let tokens = message.reply.as_ref()?.split('.').collect::<Vec<_>>();
+    let delivered = match tokens.len() {
+        | 9 => tokens[4],
+        | n if n >= 11 => tokens[6],
+        | _ => return None,
+    };
+    delivered.parse().ok()
+}
+
 trait RegexFindFirstMatching {
     fn first_regex_matches(
         &self,
         msg: &hydrogen_bus::nats::Message<hydrogen_bus::nats::ClientMessage>,
-    ) -> std::result::Result<std::option::Option<&crate::config::DestinationRoute>, Box<dyn std::error::Error>>;
+    ) -> std::result::Result<std::option::Option<(usize, &crate::config::DestinationRoute)>, Box<dyn std::error::Error>>;
 }
 
 impl RegexFindFirstMatching for std::vec::Vec<crate::config::RegexRule> {
     fn first_regex_matches(
         &self,
         msg: &hydrogen_bus::nats::Message<hydrogen_bus::nats::ClientMessage>,
-    ) -> std::result::Result<std::option::Option<&crate::config::DestinationRoute>, Box<dyn std::error::Error>> {
-        for rule in self {
+    ) -> std::result::Result<std::option::Option<(usize, &crate::config::DestinationRoute)>, Box<dyn std::error::Error>>
+    {
+        for (index, rule) in self
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.subprotocol.is_none() || v.subprotocol == msg.data.context.subprotocol)
+        {
             let regex = match fancy_regex::Regex::new(&rule.regex) {
                 | Ok(it) => it,
                 | Err(err) => return Err(Box::new(crate::error::InvalidRegexError::new(&err.to_string()))),
             };
-            if regex.is_match(&msg.data.message)? {
-                return Ok(Some(&rule.route));
+            // the base64 encoded payload of binary messages is not matched
+            let matches = match msg.data.content_type {
+                | hydrogen_bus::content::ContentType::Text => regex.is_match(&msg.data.message)?,
+                | hydrogen_bus::content::ContentType::Binary => rule.binary,
+            };
+            if matches {
+                return Ok(Some((index, &rule.route)));
             }
         }
         Ok(None)
@@ -104,6 +213,7 @@ fn handle_nats_message(
     instance: &str,
     config: &crate::config::Config,
     msg: &hydrogen_bus::nats::Message<hydrogen_bus::nats::ClientMessage>,
+    trace: &opentelemetry::Context,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
     crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
         data: crate::logger::Event::Message {
@@ -112,12 +222,18 @@ fn handle_nats_message(
     });
 
     match &config.engine_mode {
-        | config::EngineMode::Dss { rules_engine } => handle_nats_message_dss_mode(instance, msg, &rules_engine),
+        | config::EngineMode::Dss { rules_engine } => handle_nats_message_dss_mode(instance, msg, &rules_engine, trace),
         | config::EngineMode::Regex { rules } => {
             let dest = rules.first_regex_matches(msg)?;
             match dest {
-                | Some(v) => handle_nats_message_regex_mode(instance, msg, v),
+                | Some((index, v)) => {
+                    crate::metrics::RULE_MATCHES
+                        .with_label_values(&[&index.to_string()])
+                        .inc();
+                    handle_nats_message_regex_mode(instance, msg, v, trace)
+                },
                 | None => {
+                    crate::metrics::DROPPED_NO_MATCH.inc();
                     crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
                         data: crate::logger::Event::DroppedMessageNoMatch {
                             connection: &msg.data.connection_id.to_string(),
@@ -134,22 +250,37 @@ fn handle_nats_message_regex_mode(
     instance: &str,
     msg: &hydrogen_bus::nats::Message<hydrogen_bus::nats::ClientMessage>,
     destination_route: &crate::config::DestinationRoute,
+    trace: &opentelemetry::Context,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let cx = hydrogen_telemetry::start("destination", opentelemetry::trace::SpanKind::Client, trace, vec![]);
     let mut destination_req = ureq::post(&destination_route.endpoint);
-    for h in destination_route.headers.iter() {
+    for h in destination_route
+        .headers
+        .iter()
+        .chain(hydrogen_telemetry::inject(&cx).iter())
+    {
         destination_req = destination_req.set(h.0, h.1);
     }
 
-    let forward_resp = destination_req.send_string(&serde_json::to_string(&crate::routes::ForwardRequest {
+    let timer = crate::metrics::ROUTE_DURATION
+        .with_label_values(&["destination"])
+        .start_timer();
+    let res = destination_req.send_string(&serde_json::to_string(&crate::routes::ForwardRequest {
         instance_id: msg.data.instance_id.clone(),
         connection_id: msg.data.connection_id.clone(),
         endpoint: msg.data.endpoint.clone(),
         time: msg.meta.timestamp.clone(),
         context: crate::routes::MessageContext {
             authorizer: msg.data.context.authorizer.clone(),
+            principal: msg.data.context.principal.clone(),
+            subprotocol: msg.data.context.subprotocol.clone(),
         },
+        content_type: msg.data.content_type,
         message: msg.data.message.clone(),
-    })?)?;
+    })?);
+    timer.observe_duration();
+    hydrogen_telemetry::record_response(&cx, &res);
+    let forward_resp = res?;
 
     crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
         data: crate::logger::Event::DestinationRouteResponse {
@@ -171,22 +302,37 @@ fn handle_nats_message_dss_mode(
     instance: &str,
     msg: &hydrogen_bus::nats::Message<hydrogen_bus::nats::ClientMessage>,
     rules_engine_route: &crate::config::RulesEngineRoute,
+    trace: &opentelemetry::Context,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let cx = hydrogen_telemetry::start("rules_engine", opentelemetry::trace::SpanKind::Client, trace, vec![]);
     let mut re_req = ureq::post(&rules_engine_route.endpoint);
-    for (k, v) in rules_engine_route.headers.iter() {
+    for (k, v) in rules_engine_route
+        .headers
+        .iter()
+        .chain(hydrogen_telemetry::inject(&cx).iter())
+    {
         re_req = re_req.set(k, v);
     }
 
-    let re_response = re_req.send_string(&serde_json::to_string(&crate::routes::RulesEngineRequest {
+    let timer = crate::metrics::ROUTE_DURATION
+        .with_label_values(&["rules_engine"])
+        .start_timer();
+    let res = re_req.send_string(&serde_json::to_string(&crate::routes::RulesEngineRequest {
         instance_id: msg.data.instance_id.clone(),
         connection_id: msg.data.connection_id.clone(),
         endpoint: msg.data.endpoint.clone(),
         time: msg.meta.timestamp.clone(),
         context: crate::routes::MessageContext {
             authorizer: msg.data.context.authorizer.clone(),
+            principal: msg.data.context.principal.clone(),
+            subprotocol: msg.data.context.subprotocol.clone(),
         },
+        content_type: msg.data.content_type,
         message: msg.data.message.clone(),
-    })?)?;
+    })?);
+    timer.observe_duration();
+    hydrogen_telemetry::record_response(&cx, &res);
+    let re_response = res?;
 
     crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
         data: crate::logger::Event::RulesEngineRouteResponse {
@@ -203,21 +349,35 @@ fn handle_nats_message_dss_mode(
     }
     let re_response_parsed = serde_json::from_str::<crate::routes::RulesEngineResponse>(&re_response.into_string()?)?;
 
+    let cx = hydrogen_telemetry::start("destination", opentelemetry::trace::SpanKind::Client, trace, vec![]);
     let mut destination_req = ureq::post(&re_response_parsed.endpoint);
-    for h in re_response_parsed.headers.iter() {
+    for h in re_response_parsed
+        .headers
+        .iter()
+        .chain(hydrogen_telemetry::inject(&cx).iter())
+    {
         destination_req = destination_req.set(h.0, h.1);
     }
 
-    let forward_resp = destination_req.send_string(&serde_json::to_string(&crate::routes::ForwardRequest {
+    let timer = crate::metrics::ROUTE_DURATION
+        .with_label_values(&["destination"])
+        .start_timer();
+    let res = destination_req.send_string(&serde_json::to_string(&crate::routes::ForwardRequest {
         instance_id: msg.data.instance_id.clone(),
         connection_id: msg.data.connection_id.clone(),
         endpoint: msg.data.endpoint.clone(),
         time: msg.meta.timestamp.clone(),
         context: crate::routes::MessageContext {
             authorizer: msg.data.context.authorizer.clone(),
+            principal: msg.data.context.principal.clone(),
+            subprotocol: msg.data.context.subprotocol.clone(),
         },
+        content_type: msg.data.content_type,
         message: msg.data.message.clone(),
-    })?)?;
+    })?);
+    timer.observe_duration();
+    hydrogen_telemetry::record_response(&cx, &res);
+    let forward_resp = res?;
 
     crate::logger::LogMessage::now(instance, crate::logger::Data::Event {
         data: crate::logger::Event::DestinationRouteResponse {
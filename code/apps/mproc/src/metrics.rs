@@ -0,0 +1,76 @@
+use prometheus::{
+    register_histogram_vec,
+    register_int_counter,
+    register_int_counter_vec,
+    Encoder,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+};
+
+// the metrics are registered with the default registry on first use and
+// exposed through `/metrics`
+lazy_static::lazy_static! {
+    /// Messages consumed from the stream.
+    pub static ref CONSUMED: IntCounter = register_int_counter!(
+        "hydrogen_mproc_messages_consumed_total",
+        "Messages consumed from the stream."
+    )
+    .unwrap();
+    /// Messages that have been processed and acknowledged.
+    pub static ref ACKED: IntCounter = register_int_counter!(
+        "hydrogen_mproc_messages_acked_total",
+        "Messages that have been processed and acknowledged."
+    )
+    .unwrap();
+    /// Messages that failed to be processed and will be redelivered.
+    pub static ref FAILED: IntCounter = register_int_counter!(
+        "hydrogen_mproc_messages_failed_total",
+        "Messages that failed to be processed."
+    )
+    .unwrap();
+    /// Messages that have been delivered more than once.
+    pub static ref REDELIVERED: IntCounter = register_int_counter!(
+        "hydrogen_mproc_messages_redelivered_total",
+        "Messages that have been delivered more than once."
+    )
+    .unwrap();
+    /// Messages that matched none of the regex rules and have been dropped.
+    pub static ref DROPPED_NO_MATCH: IntCounter = register_int_counter!(
+        "hydrogen_mproc_messages_dropped_no_match_total",
+        "Messages that matched none of the regex rules."
+    )
+    .unwrap();
+    /// Matches per regex rule, identified by its position in the config.
+    pub static ref RULE_MATCHES: IntCounterVec = register_int_counter_vec!(
+        "hydrogen_mproc_rule_matches_total",
+        "Matches per regex rule.",
+        &["rule"]
+    )
+    .unwrap();
+    /// Latency of the rules engine and destination routes.
+    pub static ref ROUTE_DURATION: HistogramVec = register_histogram_vec!(
+        "hydrogen_mproc_route_duration_seconds",
+        "Latency of the rules engine and destination routes.",
+        &["route"]
+    )
+    .unwrap();
+}
+
+/// Registers all metrics so that they are exposed before their first use.
+pub fn init() {
+    lazy_static::initialize(&CONSUMED);
+    lazy_static::initialize(&ACKED);
+    lazy_static::initialize(&FAILED);
+    lazy_static::initialize(&REDELIVERED);
+    lazy_static::initialize(&DROPPED_NO_MATCH);
+    lazy_static::initialize(&RULE_MATCHES);
+    lazy_static::initialize(&ROUTE_DURATION);
+}
+
+/// Encodes all metrics in the prometheus text format.
+pub fn encode() -> std::result::Result<String, prometheus::Error> {
+    let mut buffer = Vec::new();
+    prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
@@ -4,6 +4,8 @@ pub type MessageContextMap = std::collections::HashMap<String, serde_json::Value
 #[serde(rename_all = "snake_case")]
 pub struct MessageContext {
     pub authorizer: std::option::Option<MessageContextMap>,
+    pub principal: std::option::Option<String>,
+    pub subprotocol: std::option::Option<String>,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -14,6 +16,7 @@ pub struct RulesEngineRequest {
     pub endpoint: String,
     pub time: String,
     pub context: MessageContext,
+    pub content_type: hydrogen_bus::content::ContentType,
     pub message: String,
 }
 
@@ -32,5 +35,6 @@ pub struct ForwardRequest {
     pub endpoint: String,
     pub time: String,
     pub context: MessageContext,
+    pub content_type: hydrogen_bus::content::ContentType,
     pub message: String,
 }
@@ -0,0 +1,18 @@
+//! Names of the redis pub/sub channels the gateway instances communicate over.
+//! Publishers and subscribers must derive channel names from here only.
+
+/// Channel all instances of a group subscribe to for broadcast messages.
+pub fn broadcast(group: &str) -> String {
+    format!("hydrogen:group:{}:broadcast", group)
+}
+
+/// Channel a single instance subscribes to for messages targeting the
+/// connections it holds.
+pub fn instance(instance: &str) -> String {
+    format!("hydrogen:instance:{}", instance)
+}
+
+/// Unique channel on which the reply to a single message is expected.
+pub fn reply(group: &str, id: &str) -> String {
+    format!("hydrogen:group:{}:reply:{}", group, id)
+}
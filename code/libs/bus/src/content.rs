@@ -0,0 +1,9 @@
+/// Marks how the `message` field of a bus message has to be interpreted.
+/// Binary payloads are transported as base64 encoded strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    #[default]
+    Text,
+    Binary,
+}
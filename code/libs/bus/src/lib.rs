@@ -1,2 +1,4 @@
+pub mod channels;
+pub mod content;
 pub mod nats;
 pub mod redis;
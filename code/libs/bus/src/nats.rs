@@ -4,6 +4,11 @@ pub type MessageContextMap = std::collections::HashMap<String, serde_json::Value
 #[serde(rename_all = "snake_case")]
 pub struct ConnectionContext {
     pub authorizer: std::option::Option<MessageContextMap>,
+    #[serde(default)]
+    pub principal: std::option::Option<String>,
+    /// Subprotocol negotiated with the client, if any.
+    #[serde(default)]
+    pub subprotocol: std::option::Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -13,6 +18,8 @@ pub struct ClientMessage {
     pub connection_id: String,
     pub endpoint: String,
     pub context: ConnectionContext,
+    #[serde(default)]
+    pub content_type: crate::content::ContentType,
     pub message: String,
 }
 
@@ -28,4 +35,7 @@ pub struct Message<T> {
 pub struct MessageMeta {
     pub id: String,
     pub timestamp: String,
+    /// W3C trace context of the hop that published the message.
+    #[serde(default)]
+    pub traceparent: std::option::Option<String>,
 }
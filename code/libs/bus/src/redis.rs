@@ -2,21 +2,69 @@
 pub enum Message {
     SBroadcast {
         time: String,
+        #[serde(default)]
+        content_type: crate::content::ContentType,
         message: String,
     },
     SEBroadcast {
         endpoint: String,
         time: String,
+        #[serde(default)]
+        content_type: crate::content::ContentType,
+        message: String,
+    },
+    /// Message to the members of a room that are owned by the receiving
+    /// instance.
+    SRBroadcast {
+        room: String,
+        connections: Vec<String>,
+        time: String,
+        #[serde(default)]
+        content_type: crate::content::ContentType,
         message: String,
     },
     S2CMessage {
         connection: String,
         time: String,
+        #[serde(default)]
+        content_type: crate::content::ContentType,
+        message: String,
+        /// Channel on which the owning instance replies once the message has
+        /// been handed to the connection.
+        #[serde(default)]
+        reply_to: std::option::Option<String>,
+    },
+    /// Message to the connections of a principal that are owned by the
+    /// receiving instance.
+    SPMessage {
+        principal: String,
+        connections: Vec<String>,
+        time: String,
+        #[serde(default)]
+        content_type: crate::content::ContentType,
         message: String,
     },
+    /// Disconnect of the connections of a principal that are owned by the
+    /// receiving instance.
+    SPDisconnect {
+        principal: String,
+        connections: Vec<String>,
+        time: String,
+        reason: String,
+    },
     SDisconnect {
         connection: String,
         time: String,
         reason: String,
     },
 }
+
+/// Reply that is published on the `reply_to` channel of a message.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Delivered,
+    ConnectionNotFound,
+    /// The message exceeds the outbound size limit of the connection.
+    TooLarge,
+}
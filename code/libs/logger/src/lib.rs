@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        Arc,
+        RwLock,
+    },
+};
+
+hydrogen_error::make_error!(ConfigError);
+
+/// Environment variable overriding the configured filter.
+pub const FILTER_ENV: &str = "HYDROGEN_LOG";
+
+static SETTINGS: RwLock<std::option::Option<Arc<Settings>>> = RwLock::new(None);
+
+/// Applies the logging configuration found at `key` (e.g. `server.logging`),
+/// messages logged before use the defaults.
+pub fn init(
+    key: &str,
+    filter: std::option::Option<&str>,
+    format: Format,
+    sampling: HashMap<String, f64>,
+) -> std::result::Result<(), ConfigError> {
+    let filter = match std::env::var(FILTER_ENV) {
+        | Ok(v) => v.parse::<Filter>()?,
+        | Err(..) => filter.unwrap_or_default().parse::<Filter>()?,
+    };
+    if let Some((name, ratio)) = sampling.iter().find(|(_, v)| !(0.0..=1.0).contains(*v)) {
+        return Err(ConfigError::new(&format!(
+            "{}.sampling.{} ({}) must be within 0 and 1",
+            key, name, ratio
+        )));
+    }
+    *SETTINGS.write().unwrap() = Some(Arc::new(Settings {
+        filter,
+        format,
+        sampling,
+    }));
+    Ok(())
+}
+
+/// Returns the settings applied by `init`.
+pub fn settings() -> Arc<Settings> {
+    SETTINGS.read().unwrap().clone().unwrap_or_default()
+}
+
+#[derive(Debug, Default)]
+pub struct Settings {
+    filter: Filter,
+    format: Format,
+    sampling: HashMap<String, f64>,
+}
+
+impl Settings {
+    /// Decides whether a message of the given event and level is written.
+    pub fn enabled(&self, name: &str, level: Level) -> bool {
+        if level > self.filter.level_for(name) {
+            return false;
+        }
+        match self.sampling.get(name) {
+            | Some(v) => rand::random::<f64>() < *v,
+            | None => true,
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+/// The severity of a log message. `off` only applies to filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            | "off" => Ok(Self::Off),
+            | "error" => Ok(Self::Error),
+            | "warn" => Ok(Self::Warn),
+            | "info" => Ok(Self::Info),
+            | "debug" => Ok(Self::Debug),
+            | "trace" => Ok(Self::Trace),
+            | _ => Err(ConfigError::new(&format!("unknown log level {}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            | Self::Off => "OFF",
+            | Self::Error => "ERROR",
+            | Self::Warn => "WARN",
+            | Self::Info => "INFO",
+            | Self::Debug => "DEBUG",
+            | Self::Trace => "TRACE",
+        })
+    }
+}
+
+/// A default level followed by per event overrides, such as
+/// `info,client_message=debug,connections=off`.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    level: Level,
+    events: HashMap<String, Level>,
+}
+
+impl Filter {
+    fn level_for(&self, name: &str) -> Level {
+        self.events.get(name).copied().unwrap_or(self.level)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            events: HashMap::new(),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut filter = Self::default();
+        for directive in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            match directive.split_once('=') {
+                | Some((name, level)) => {
+                    filter.events.insert(name.trim().to_owned(), level.trim().parse()?);
+                },
+                | None => filter.level = directive.parse()?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// The output format of the log messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// Human-readable lines for local development.
+    Text,
+}
+
+/// Renders a message as `time LEVEL instance event key=value...`, the pairs
+/// being the fields of the given event.
+pub fn text<T: serde::Serialize>(time: &str, level: Level, instance: &str, name: &str, event: &T) -> String {
+    let mut line = format!("{} {:<5} {} {}", time, level, instance, name);
+    if let Ok(serde_json::Value::Object(variant)) = serde_json::to_value(event) {
+        for (_, fields) in variant {
+            if let serde_json::Value::Object(fields) = fields {
+                for (k, v) in fields {
+                    match v {
+                        | serde_json::Value::String(s) if !s.is_empty() && !s.contains(char::is_whitespace) => {
+                            line.push_str(&format!(" {}={}", k, s))
+                        },
+                        | v => line.push_str(&format!(" {}={}", k, v)),
+                    }
+                }
+            }
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_applies_event_overrides() {
+        let filter = "warn, client_message = debug,connections=off"
+            .parse::<Filter>()
+            .unwrap();
+        assert_eq!(filter.level_for("connect"), Level::Warn);
+        assert_eq!(filter.level_for("client_message"), Level::Debug);
+        assert_eq!(filter.level_for("connections"), Level::Off);
+    }
+
+    #[test]
+    fn filter_rejects_unknown_levels() {
+        assert!("verbose".parse::<Filter>().is_err());
+        assert!("info,connect=loud".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+};
+
+use opentelemetry::{
+    global,
+    trace::{
+        SpanKind,
+        Status,
+        TraceContextExt,
+        Tracer,
+    },
+    Context,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{
+        Sampler,
+        SdkTracerProvider,
+    },
+    Resource,
+};
+
+/// Name of the service the spans are started by, set once on `init`.
+static SERVICE: OnceLock<&'static str> = OnceLock::new();
+
+/// Export of the traces to an OpenTelemetry collector.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Tracing {
+    /// OTLP/HTTP traces endpoint, the `OTEL_EXPORTER_OTLP_*` environment
+    /// variables apply if missing.
+    #[serde(default)]
+    pub endpoint: std::option::Option<String>,
+    /// Ratio of the traces started by this instance that are sampled.
+    #[serde(default)]
+    pub sample_ratio: std::option::Option<f64>,
+}
+
+/// Installs the OTLP exporter for the given service (e.g. `hydrogen-gateway`)
+/// if tracing is configured. The W3C trace context is propagated either way
+/// so that incoming traces are continued by the downstream services.
+pub fn init(
+    service: &'static str,
+    instance: &str,
+    group: &str,
+    settings: std::option::Option<&Tracing>,
+) -> std::result::Result<std::option::Option<SdkTracerProvider>, Box<dyn std::error::Error>> {
+    SERVICE.get_or_init(|| service);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let settings = match settings {
+        | Some(v) => v,
+        | None => return Ok(None),
+    };
+
+    let mut exporter = opentelemetry_otlp::SpanExporter::builder().with_http();
+    if let Some(endpoint) = &settings.endpoint {
+        exporter = exporter.with_endpoint(endpoint);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter.build()?)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sample_ratio.unwrap_or(1.0),
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service)
+                .with_attributes([
+                    KeyValue::new("hydrogen.instance_id", instance.to_owned()),
+                    KeyValue::new("hydrogen.group_id", group.to_owned()),
+                ])
+                .build(),
+        )
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+/// Starts a span as child of `parent`. The span ends once the returned
+/// context and all of its clones are dropped.
+pub fn start(name: &'static str, kind: SpanKind, parent: &Context, attributes: Vec<KeyValue>) -> Context {
+    let tracer = global::tracer(SERVICE.get().copied().unwrap_or("hydrogen"));
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+    parent.with_span(span)
+}
+
+/// Reads the trace context from the given fields, i.e. the headers of a
+/// request or of a message.
+pub fn extract<'a>(headers: impl Iterator<Item=(&'a str, &'a str)>) -> Context {
+    let carrier = headers
+        .map(|(k, v)| (k.to_lowercase(), v.to_owned()))
+        .collect::<HashMap<_, _>>();
+    global::get_text_map_propagator(|p| p.extract(&carrier))
+}
+
+/// Returns the headers carrying the trace context, i.e. `traceparent`.
+pub fn inject(cx: &Context) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|p| p.inject_context(cx, &mut carrier));
+    carrier
+}
+
+/// Records the status code a route responded with, marking the span as
+/// failed for anything but 200.
+pub fn record_status(cx: &Context, status: u16) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("http.response.status_code", i64::from(status)));
+    if status != 200 {
+        span.set_status(Status::error(format!("status code {}", status)));
+    }
+}
+
+/// Marks the span as failed.
+pub fn record_error(cx: &Context, err: &dyn std::error::Error) {
+    cx.span().set_status(Status::error(err.to_string()));
+}
+
+/// Records the outcome of a route call.
+pub fn record_response(cx: &Context, res: &std::result::Result<ureq::Response, ureq::Error>) {
+    match res {
+        | Ok(v) => record_status(cx, v.status()),
+        | Err(ureq::Error::Status(code, _)) => record_status(cx, *code),
+        | Err(e) => record_error(cx, e),
+    }
+}